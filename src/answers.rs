@@ -0,0 +1,80 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context as _, Result};
+use toml::{Table, Value};
+
+pub(crate) const ANSWERS_FILE: &str = ".tapgen.answers.toml";
+
+pub(crate) struct Answers {
+    pub(crate) name: String,
+    pub(crate) values: Table,
+    pub(crate) header: Table, // dunder keys, e.g. `__source__`
+}
+
+impl Answers {
+    pub(crate) fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .context(format!("failed to read answers file: '{}'", path.display()))?;
+        let table = contents.parse::<Table>().context(format!(
+            "failed to parse answers file: '{}'",
+            path.display()
+        ))?;
+        let (header, values) = table
+            .into_iter()
+            .partition(|(name, _)| name.starts_with("__") && name.ends_with("__"));
+        Ok(Self {
+            name: path.file_stem().unwrap().to_string_lossy().to_string(),
+            values,
+            header,
+        })
+    }
+
+    /// Saves `values` along with where they came from, so that the generation can be replayed.
+    pub(crate) fn save(
+        path: impl AsRef<Path>,
+        source: &str,
+        commit: Option<&str>,
+        name: &str,
+        values: Table,
+    ) -> Result<()> {
+        let mut path = path.as_ref().to_path_buf();
+        if path.is_dir() {
+            path.push(ANSWERS_FILE);
+        }
+        let mut table = Table::new();
+        table.insert(String::from("__source__"), Value::from(source));
+        if let Some(commit) = commit {
+            table.insert(String::from("__commit__"), Value::from(commit));
+        }
+        table.insert(String::from("__name__"), Value::from(name));
+        table.extend(values);
+        fs::write(&path, toml::to_string_pretty(&table)?).context(format!(
+            "failed to write answers file: '{}'",
+            path.display()
+        ))?;
+        log::info!("saved answers to '{}'", path.display());
+        Ok(())
+    }
+
+    pub(crate) fn header_str(&self, key: &str) -> Option<&str> {
+        self.header.get(key).and_then(Value::as_str)
+    }
+
+    pub(crate) fn load_dir(dir: impl AsRef<Path>) -> Result<Vec<Self>> {
+        let dir = dir.as_ref();
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir).context(format!(
+            "failed to read answers directory: '{}'",
+            dir.display()
+        ))? {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "toml") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        paths.iter().map(Self::load).collect()
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _, Error, Result};
+use flate2::read::GzDecoder;
+
+use tapgen::template;
+
+use crate::net;
+use crate::pack::sha256_hex;
+
+const ARCHIVES_DIR: &str = ".archives";
+
+#[derive(Clone, Copy)]
+pub(crate) enum Kind {
+    TarGz,
+    Zip,
+}
+
+impl Kind {
+    pub(crate) fn detect(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Extracts the archive into `dst`, refusing entries outside of it.
+pub(crate) fn extract(reader: impl Read + Seek, kind: Kind, dst: &Path) -> Result<()> {
+    match kind {
+        Kind::TarGz => tar::Archive::new(GzDecoder::new(reader))
+            .unpack(dst)
+            .context("failed to extract tar.gz archive"),
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(reader).context("failed to read zip archive")?;
+            for i in 0..archive.len() {
+                let mut file = archive.by_index(i)?;
+                let Some(name) = file.enclosed_name().map(Path::to_path_buf) else {
+                    bail!("unsafe path in zip archive: '{}'", file.name())
+                };
+                let path = dst.join(name);
+                if file.is_dir() {
+                    fs::create_dir_all(&path)?;
+                    continue;
+                }
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                io::copy(&mut file, &mut File::create(&path)?)
+                    .context(format!("failed to extract file: '{}'", path.display()))?;
+                #[cfg(unix)]
+                if let Some(mode) = file.unix_mode() {
+                    use std::os::unix::fs::PermissionsExt as _;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+                }
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Root of the extracted template: archives of a single top-level directory
+/// (like release tarballs) are rooted at that directory.
+pub(crate) fn template_root(dir: &Path) -> Result<PathBuf> {
+    if template::definition_path(dir).exists() {
+        return Ok(dir.to_path_buf());
+    }
+    let entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    if let [entry] = entries.as_slice() {
+        if entry.file_type()?.is_dir() {
+            return Ok(entry.path());
+        }
+    }
+    Ok(dir.to_path_buf())
+}
+
+/// Extracts the archive into the cache under `prefix`, keyed by `key`,
+/// returning the extracted directory.
+pub(crate) fn extract_cached(
+    prefix: &Path,
+    key: &str,
+    kind: Kind,
+    reader: impl Read + Seek,
+) -> Result<PathBuf> {
+    let dst = prefix
+        .join(ARCHIVES_DIR)
+        .join(&sha256_hex(key.as_bytes())[..16]);
+    let partial = dst.with_extension("partial");
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)?;
+    extract(reader, kind, &partial)?;
+    if dst.exists() {
+        fs::remove_dir_all(&dst)?;
+    }
+    fs::rename(&partial, &dst).context(format!(
+        "failed to move extracted archive to '{}'",
+        dst.display()
+    ))?;
+    Ok(dst)
+}
+
+/// Extracts the local archive at `path` into the prefix, again only once it has changed.
+pub(crate) fn resolve_local(prefix: impl AsRef<Path>, path: &Path) -> Result<Option<PathBuf>> {
+    let Some(kind) = Kind::detect(&path.to_string_lossy()) else {
+        return Ok(None);
+    };
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let path = path.canonicalize()?;
+    let metadata = fs::metadata(&path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let key = format!(
+        "{}:{}:{}",
+        path.display(),
+        metadata.len(),
+        modified.as_nanos()
+    );
+    let prefix = prefix.as_ref();
+    let dst = prefix
+        .join(ARCHIVES_DIR)
+        .join(&sha256_hex(key.as_bytes())[..16]);
+    if dst.exists() {
+        return template_root(&dst).map(Some);
+    }
+    say!("Extracting '{}'...", path.display());
+    let file =
+        File::open(&path).context(format!("failed to open archive: '{}'", path.display()))?;
+    let dst = extract_cached(prefix, &key, kind, file)?;
+    template_root(&dst).map(Some)
+}
+
+#[derive(Clone)]
+pub(crate) struct Source {
+    url: String,
+    kind: Kind,
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.url.fmt(f)
+    }
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if !(s.starts_with("https://") || s.starts_with("http://")) {
+            bail!("mismatched archive source pattern")
+        }
+        let path = s.split(['?', '#']).next().unwrap();
+        let Some(kind) = Kind::detect(path) else {
+            bail!("unsupported archive format: '{s}' (expected .tar.gz, .tgz or .zip)")
+        };
+        Ok(Self {
+            url: s.to_string(),
+            kind,
+        })
+    }
+}
+
+impl Source {
+    fn cache_path(&self, prefix: &Path) -> PathBuf {
+        prefix
+            .join(ARCHIVES_DIR)
+            .join(&sha256_hex(self.url.as_bytes())[..16])
+    }
+
+    /// Root of the template extracted from the archive under the prefix.
+    pub(crate) fn local_path(&self, prefix: impl AsRef<Path>) -> Result<PathBuf> {
+        template_root(&self.cache_path(prefix.as_ref()))
+    }
+
+    /// Downloads and extracts the archive again, replacing the cached one, without progress output.
+    pub(crate) fn fetch(&self, prefix: impl AsRef<Path>) -> Result<()> {
+        let buf = net::fetch(&self.url)?;
+        extract_cached(prefix.as_ref(), &self.url, self.kind, io::Cursor::new(buf))?;
+        Ok(())
+    }
+
+    /// Downloads and extracts the archive unless it is cached, without progress output,
+    /// returning whether it was downloaded.
+    pub(crate) fn prefetch(&self, prefix: impl AsRef<Path>) -> Result<bool> {
+        let prefix = prefix.as_ref();
+        if self.cache_path(prefix).exists() {
+            return Ok(false);
+        }
+        self.fetch(prefix)?;
+        Ok(true)
+    }
+
+    /// Downloads and extracts the archive into the prefix, reusing a previous download.
+    pub(crate) fn resolve(&self, prefix: impl AsRef<Path>) -> Result<PathBuf> {
+        let prefix = prefix.as_ref();
+        let dst = self.cache_path(prefix);
+        if dst.exists() {
+            say!("Archive already downloaded: '{}'", dst.display());
+            say!("Run `tapgen get {}` to download it again.", self.url);
+            return template_root(&dst);
+        }
+        say!("Downloading '{}'...", self.url);
+        self.prefetch(prefix)?;
+        say!();
+        template_root(&dst)
+    }
+}
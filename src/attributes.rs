@@ -0,0 +1,66 @@
+use std::fs;
+use std::path::Path;
+
+use glob::Pattern;
+
+/// Collects the paths marked `export-ignore` in `.gitattributes` files of the repository
+/// containing `root` (from `root` up to the top of the repository), as glob patterns relative to `root`.
+pub(crate) fn export_ignore_patterns(root: &Path) -> Vec<Pattern> {
+    let mut patterns = Vec::new();
+    let Some(top) = root.ancestors().find(|dir| dir.join(".git").exists()) else {
+        return patterns;
+    };
+    for dir in root.ancestors() {
+        let relative = root.strip_prefix(dir).unwrap();
+        let relative = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if let Ok(contents) = fs::read_to_string(dir.join(".gitattributes")) {
+            for line in contents.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let mut fields = line.split_whitespace();
+                let Some(pattern) = fields.next() else {
+                    continue;
+                };
+                if !fields.any(|attribute| attribute == "export-ignore") {
+                    continue;
+                }
+                let pattern = pattern.trim_end_matches('/');
+                let globs = if !pattern.contains('/') {
+                    // like gitignore, a pattern without slashes matches at any level
+                    vec![
+                        pattern.to_string(),
+                        format!("{pattern}/**"),
+                        format!("**/{pattern}"),
+                        format!("**/{pattern}/**"),
+                    ]
+                } else {
+                    let pattern = pattern.trim_start_matches('/');
+                    let pattern = if relative.is_empty() {
+                        pattern
+                    } else {
+                        match pattern.strip_prefix(&format!("{relative}/")) {
+                            Some(pattern) => pattern,
+                            None => continue, // outside of the template
+                        }
+                    };
+                    vec![pattern.to_string(), format!("{pattern}/**")]
+                };
+                for glob in globs {
+                    match Pattern::new(&glob) {
+                        Ok(glob) => patterns.push(glob),
+                        Err(err) => log::warn!("invalid export-ignore pattern '{glob}': {err}"),
+                    }
+                }
+            }
+        }
+        if dir == top {
+            break;
+        }
+    }
+    patterns
+}
@@ -0,0 +1,674 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context as _, Error, Result};
+use chrono::{Local, TimeZone as _};
+use clap::{Args, Subcommand};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tapgen::metadata::Metadata;
+use walkdir::WalkDir;
+
+use crate::config::Config;
+use crate::git::{Host, Repository, LAST_USED_KEY};
+use crate::pack::sha256_hex;
+use crate::prefix::Index;
+use crate::prompt;
+
+pub(crate) struct Cached {
+    pub(crate) host: String, // name of the host directory, e.g. `github`
+    pub(crate) owner: String,
+    pub(crate) repo: String,
+    pub(crate) repository: Repository,
+}
+
+impl std::fmt::Display for Cached {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}/{}", self.host, self.owner, self.repo)
+    }
+}
+
+impl Cached {
+    /// Path of the repository relative to the prefix, separated by `/`.
+    pub(crate) fn relative(&self) -> String {
+        format!("{}/{}/{}", self.host, self.owner, self.repo)
+    }
+}
+
+pub(crate) fn repositories(prefix: impl AsRef<Path>) -> Result<Vec<Cached>> {
+    fn subdirs(path: &Path) -> Result<Vec<PathBuf>> {
+        let mut dirs = Vec::new();
+        for entry in
+            fs::read_dir(path).context(format!("failed to read directory: '{}'", path.display()))?
+        {
+            let entry = entry?;
+            let name = entry.file_name();
+            if entry.file_type()?.is_dir() && !name.to_string_lossy().starts_with('.') {
+                dirs.push(entry.path());
+            }
+        }
+        dirs.sort();
+        Ok(dirs)
+    }
+
+    fn collect(prefix: &Path, dir: &Path, cached: &mut Vec<Cached>) -> Result<()> {
+        for path in subdirs(dir)? {
+            if path.join(".git").exists() {
+                // repositories lie below their host and at least one level (owner or groups)
+                let mut components = dir
+                    .strip_prefix(prefix)
+                    .unwrap()
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().to_string());
+                let host = components.next();
+                let owner = components.collect::<Vec<_>>().join("/");
+                if let Some(host) = host.filter(|_| !owner.is_empty()) {
+                    cached.push(Cached {
+                        host,
+                        owner,
+                        repo: path.file_name().unwrap().to_string_lossy().to_string(),
+                        repository: Repository::new(&path),
+                    });
+                }
+            } else {
+                collect(prefix, &path, cached)?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut cached = Vec::new();
+    collect(prefix.as_ref(), prefix.as_ref(), &mut cached)?;
+    Ok(cached)
+}
+
+const CATALOG_FILE: &str = ".tapgen.cache.toml";
+
+/// Summary of a cached template, kept so that listing does not parse every template.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct CatalogEntry {
+    pub(crate) path: String, // relative to prefix
+    pub(crate) name: String,
+    pub(crate) author: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) description: Option<String>,
+    pub(crate) variables: String, // digest of variable definitions
+    modified: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) source: Option<String>, // fetched from, if not told by the path (e.g. of archives)
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Catalog {
+    #[serde(rename = "template", default)]
+    pub(crate) entries: Vec<CatalogEntry>,
+}
+
+fn modified_secs(path: &Path) -> Result<u64> {
+    let modified = fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .context(format!(
+            "failed to read modified time: '{}'",
+            path.display()
+        ))?;
+    Ok(modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0))
+}
+
+impl Catalog {
+    pub(crate) fn load(prefix: impl AsRef<Path>) -> Result<Self> {
+        let path = prefix.as_ref().join(CATALOG_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .context(format!("failed to read catalog: '{}'", path.display()))?;
+        match toml::from_str(&contents) {
+            Ok(catalog) => Ok(catalog),
+            Err(err) => {
+                // the catalog is only a cache, so a corrupted one is rebuilt from scratch
+                log::warn!("discarding malformed catalog '{}': {err}", path.display());
+                Ok(Self::default())
+            }
+        }
+    }
+
+    pub(crate) fn save(&self, prefix: impl AsRef<Path>) -> Result<()> {
+        let path = prefix.as_ref().join(CATALOG_FILE);
+        fs::write(&path, toml::to_string_pretty(self)?)
+            .context(format!("failed to write catalog: '{}'", path.display()))
+    }
+
+    /// Records the template at `path` (a `tapgen.toml` file under `prefix`) fetched from `source`,
+    /// keeping the source recorded before if not given.
+    pub(crate) fn record(
+        &mut self,
+        prefix: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        source: Option<String>,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let relative = path
+            .strip_prefix(prefix.as_ref())
+            .context(format!(
+                "template is not under prefix: '{}'",
+                path.display()
+            ))?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let contents = fs::read_to_string(path)
+            .context(format!("failed to read template: '{}'", path.display()))?;
+        let metadata = toml::from_str::<Metadata>(&contents)
+            .context(format!("failed to parse template: '{}'", path.display()))?;
+        let variables = contents
+            .parse::<toml::Table>()?
+            .into_iter()
+            .filter(|(name, _)| !(name.starts_with("__") && name.ends_with("__")))
+            .collect::<toml::Table>();
+        let entry = CatalogEntry {
+            path: relative,
+            name: metadata.name,
+            author: metadata.author,
+            description: metadata.description,
+            variables: sha256_hex(variables.to_string().as_bytes()),
+            modified: modified_secs(path)?,
+            source,
+        };
+        match self.entries.iter_mut().find(|e| e.path == entry.path) {
+            Some(existing) => {
+                let source = entry.source.or(existing.source.take());
+                *existing = CatalogEntry { source, ..entry }
+            }
+            None => self.entries.push(entry),
+        }
+        Ok(())
+    }
+
+    /// Drops entries of removed templates and re-records modified ones.
+    /// Returns whether the catalog has changed.
+    pub(crate) fn refresh(&mut self, prefix: impl AsRef<Path>) -> Result<bool> {
+        let prefix = prefix.as_ref();
+        let mut changed = false;
+        let mut stale = Vec::new();
+        self.entries.retain(|entry| {
+            let path = prefix.join(&entry.path);
+            match modified_secs(&path) {
+                Ok(modified) if modified == entry.modified => true,
+                Ok(_) => {
+                    stale.push(path);
+                    false
+                }
+                Err(_) => {
+                    changed = true;
+                    false
+                }
+            }
+        });
+        for path in stale {
+            changed = true;
+            if let Err(err) = self.record(prefix, &path, None) {
+                log::warn!("cannot record '{}' in catalog: {err:#}", path.display());
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// Finds every template under the prefix, skipping hidden directories (e.g. `.git`).
+fn templates(prefix: &Path) -> Vec<PathBuf> {
+    WalkDir::new(prefix)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        })
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file() && entry.file_name() == "tapgen.toml")
+        .map(|entry| entry.into_path())
+        .collect()
+}
+
+/// Records the resolved template fetched from `source` in the catalog of the prefix,
+/// if it lies under the prefix.
+pub(crate) fn update_catalog(
+    prefix: impl AsRef<Path>,
+    path: impl AsRef<Path>,
+    source: Option<String>,
+) -> Result<()> {
+    let prefix = prefix.as_ref().canonicalize()?;
+    if !path.as_ref().starts_with(&prefix) {
+        return Ok(());
+    }
+    let mut catalog = Catalog::load(&prefix)?;
+    catalog.record(&prefix, path, source)?;
+    catalog.save(&prefix)
+}
+
+#[derive(Clone)]
+pub(crate) struct Pattern {
+    host: Option<Host>,
+    owner: glob::Pattern,
+    repo: glob::Pattern,
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+        let pattern = PATTERN.get_or_init(|| {
+            Regex::new(r"^((?<host>github|gitlab|bitbucket):)?(?<owner>.+)\/(?<repo>[^\/]+)$")
+                .unwrap()
+        });
+        let Some(captures) = pattern.captures(s) else {
+            bail!("mismatched repository pattern: '{s}' (expected '[<host>:]<owner>/<repo>')")
+        };
+        Ok(Self {
+            host: captures
+                .name("host")
+                .map(|m| Host::from_str(m.as_str()).unwrap()),
+            owner: glob::Pattern::new(captures.name("owner").unwrap().as_str())?,
+            repo: glob::Pattern::new(captures.name("repo").unwrap().as_str())?,
+        })
+    }
+}
+
+impl Pattern {
+    pub(crate) fn matches(&self, cached: &Cached) -> bool {
+        // repositories are cached under the directory of their host
+        if let Some(host) = &self.host {
+            if cached.host != host.name() {
+                return false;
+            }
+        }
+        self.owner.matches(&cached.owner) && self.repo.matches(&cached.repo)
+    }
+}
+
+pub(crate) fn matching(prefix: impl AsRef<Path>, patterns: &[Pattern]) -> Result<Vec<Cached>> {
+    let mut matched = Vec::new();
+    for cached in repositories(prefix)? {
+        let mut is_match = patterns.is_empty();
+        for pattern in patterns {
+            if pattern.matches(&cached) {
+                is_match = true;
+                break;
+            }
+        }
+        if is_match {
+            matched.push(cached);
+        }
+    }
+    Ok(matched)
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Remove {
+    #[arg(
+        required = true,
+        help = "Patterns of cached repositories to be removed, e.g. 'github:owner/*'.",
+        value_parser = Pattern::from_str,
+    )]
+    patterns: Vec<Pattern>,
+}
+
+impl Remove {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let matched = matching(&config.prefix, &self.patterns)?;
+        if matched.is_empty() {
+            println!("No cached repositories matched.");
+            return Ok(());
+        }
+        println!("[Matched]");
+        for cached in &matched {
+            println!("│ {cached}");
+        }
+        if !prompt::confirm(
+            format!("Remove {} repositories?", matched.len()),
+            Some(false),
+        ) {
+            return Ok(());
+        }
+        for cached in &matched {
+            let path = cached.repository.path();
+            fs::remove_dir_all(path)
+                .context(format!("failed to remove repository: '{}'", path.display()))?;
+            log::info!("removed cached repository '{}'", path.display());
+        }
+        println!("Removed {} repositories.", matched.len());
+        Ok(())
+    }
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Pin {
+    #[arg(
+        required = true,
+        help = "Patterns of cached repositories to be pinned, e.g. 'github:owner/*'.",
+        value_parser = Pattern::from_str,
+    )]
+    patterns: Vec<Pattern>,
+    #[arg(long = "unpin", help = "Unpin instead of pin.")]
+    unpin: bool,
+}
+
+impl Pin {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let matched = matching(&config.prefix, &self.patterns)?;
+        if matched.is_empty() {
+            println!("No cached repositories matched.");
+            return Ok(());
+        }
+        for cached in &matched {
+            if self.unpin {
+                cached.repository.unset_config("tapgen.pin")?;
+                println!("Unpinned {cached}.");
+            } else {
+                let head = cached.repository.head()?;
+                cached.repository.set_config("tapgen.pin", &head)?;
+                println!("Pinned {cached} at {head}.");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct List {
+    #[arg(
+        long = "rescan",
+        help = "Rebuild the catalog by searching the whole prefix for templates."
+    )]
+    rescan: bool,
+}
+
+impl List {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let prefix = config.prefix.canonicalize()?;
+        let mut catalog = Catalog::load(&prefix)?;
+        let mut changed = catalog.refresh(&prefix)?;
+        // templates are catalogued as they are fetched, so the prefix is only searched on demand
+        for path in self.rescan.then(|| templates(&prefix)).unwrap_or_default() {
+            let relative = path.strip_prefix(&prefix).unwrap();
+            let relative = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if catalog.entries.iter().any(|entry| entry.path == relative) {
+                continue;
+            }
+            changed = true;
+            if let Err(err) = catalog.record(&prefix, &path, None) {
+                log::warn!("cannot record '{}' in catalog: {err:#}", path.display());
+            }
+        }
+        if changed {
+            catalog.save(&prefix)?;
+        }
+        let index = Index::load(&prefix)?;
+        if catalog.entries.is_empty() && index.entries.is_empty() {
+            println!("No cached templates.");
+            return Ok(());
+        }
+        if !index.entries.is_empty() {
+            println!("[Index]");
+            for entry in &index.entries {
+                println!("│ @:{}: {}", entry.name, entry.source);
+                if let Some(description) = &entry.description {
+                    println!("│     {description}");
+                }
+            }
+        }
+        if catalog.entries.is_empty() {
+            return Ok(());
+        }
+        let repositories = repositories(&prefix)?;
+        catalog.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        println!("[Templates]");
+        for entry in &catalog.entries {
+            let dir = entry
+                .path
+                .trim_end_matches("tapgen.toml")
+                .trim_end_matches('/');
+            let cached = repositories.iter().find(|cached| {
+                let repo = cached.relative();
+                dir == repo || dir.starts_with(&format!("{repo}/"))
+            });
+            let source = match (&entry.source, cached) {
+                (Some(source), _) => source.clone(),
+                // nested groups are separated from the path inside the repository by `//`
+                (None, Some(cached)) => match &dir[cached.relative().len()..] {
+                    "" if cached.owner.contains('/') => format!("{cached}//"),
+                    path if cached.owner.contains('/') => format!("{cached}/{path}"),
+                    path => format!("{cached}{path}"),
+                },
+                (None, None) => format!("@:{dir}"),
+            };
+            println!("│ {source}: {} by {}", entry.name, entry.author);
+            if let Some(description) = &entry.description {
+                println!("│     {description}");
+            }
+        }
+        Ok(())
+    }
+}
+
+fn disk_usage(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.metadata().map(|metadata| metadata.len()).unwrap_or(0))
+        .sum()
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Cache {
+    #[command(subcommand)]
+    command: CacheCommand,
+}
+
+#[derive(Clone, Subcommand)]
+enum CacheCommand {
+    #[command(about = "Summarize cached templates and their disk usage.")]
+    Stats(CacheStats),
+}
+
+impl Cache {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        match &self.command {
+            CacheCommand::Stats(stats) => stats.run(config),
+        }
+    }
+}
+
+#[derive(Clone, Args)]
+struct CacheStats {
+    #[arg(
+        long = "gc",
+        help = "Remove least recently used repositories exceeding `max_cache_bytes`."
+    )]
+    gc: bool,
+}
+
+struct Usage {
+    cached: Cached,
+    bytes: u64,
+    last_used: Option<u64>,
+    pin: Option<String>,
+}
+
+impl CacheStats {
+    fn run(&self, config: &Config) -> Result<()> {
+        let prefix = config.prefix.canonicalize()?;
+        let mut usages = Vec::new();
+        for cached in repositories(&prefix)? {
+            let last_used = cached
+                .repository
+                .get_config(LAST_USED_KEY)?
+                .and_then(|secs| secs.parse().ok());
+            let pin = cached.repository.get_config("tapgen.pin")?;
+            let bytes = disk_usage(cached.repository.path());
+            usages.push(Usage {
+                cached,
+                bytes,
+                last_used,
+                pin,
+            });
+        }
+        // most recently used first
+        usages.sort_by(|a, b| b.last_used.cmp(&a.last_used));
+
+        let total = disk_usage(&prefix);
+        println!("Templates: {}", templates(&prefix).len());
+        println!("Repositories: {}", usages.len());
+        print!("Disk usage: {total} bytes");
+        match config.max_cache_bytes {
+            Some(budget) => println!(" (budget: {budget} bytes)"),
+            None => println!(),
+        }
+        if !usages.is_empty() {
+            println!();
+            println!("[Repositories]");
+        }
+        for usage in &usages {
+            let last_used = match usage.last_used {
+                Some(secs) => Local
+                    .timestamp_opt(secs as i64, 0)
+                    .single()
+                    .map(|time| time.format("%Y-%m-%d %H:%M").to_string())
+                    .unwrap_or_default(),
+                None => String::from("never"),
+            };
+            print!(
+                "│ {}: {} bytes, last used {last_used}",
+                usage.cached, usage.bytes
+            );
+            match &usage.pin {
+                Some(pin) => println!(", pinned at {pin}"),
+                None => println!(),
+            }
+        }
+
+        if !self.gc {
+            return Ok(());
+        }
+        let Some(budget) = config.max_cache_bytes else {
+            bail!("no cache budget to collect garbage for (set `max_cache_bytes` in config)")
+        };
+        let mut total = total;
+        let mut evicted = Vec::new();
+        // pinned repositories are kept, as they were deliberately held at a revision
+        for usage in usages.iter().rev().filter(|usage| usage.pin.is_none()) {
+            if total <= budget {
+                break;
+            }
+            total = total.saturating_sub(usage.bytes);
+            evicted.push(usage);
+        }
+        println!();
+        if evicted.is_empty() {
+            println!("Cache is within budget.");
+            return Ok(());
+        }
+        println!("[Least Recently Used]");
+        for usage in &evicted {
+            println!("│ {} ({} bytes)", usage.cached, usage.bytes);
+        }
+        if !prompt::confirm(
+            format!("Remove {} repositories?", evicted.len()),
+            Some(false),
+        ) {
+            return Ok(());
+        }
+        for usage in &evicted {
+            let path = usage.cached.repository.path();
+            fs::remove_dir_all(path)
+                .context(format!("failed to remove repository: '{}'", path.display()))?;
+            log::info!(
+                "removed least recently used repository '{}'",
+                path.display()
+            );
+        }
+        let mut catalog = Catalog::load(&prefix)?;
+        if catalog.refresh(&prefix)? {
+            catalog.save(&prefix)?;
+        }
+        println!("Removed {} repositories.", evicted.len());
+        if total > budget {
+            println!("Cache still exceeds budget ({total} bytes), due to pinned repositories or other files.");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Update {
+    #[arg(
+        help = "Patterns of cached repositories to be updated (default: all).",
+        value_parser = Pattern::from_str,
+    )]
+    patterns: Vec<Pattern>,
+}
+
+impl Update {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let matched = matching(&config.prefix, &self.patterns)?;
+        if matched.is_empty() {
+            println!("No cached repositories matched.");
+            return Ok(());
+        }
+        let (mut updated, mut failed) = (Vec::new(), Vec::new());
+        for cached in &matched {
+            if let Some(pin) = cached.repository.get_config("tapgen.pin")? {
+                println!("{cached}: pinned at {pin}.");
+                continue;
+            }
+            let result = cached
+                .repository
+                .check_fastforwardable()
+                .and_then(|outdated| {
+                    if outdated {
+                        cached.repository.pull(false)?;
+                    }
+                    Ok(outdated)
+                });
+            match result {
+                Ok(true) => {
+                    println!("{cached}: updated.");
+                    updated.push(cached);
+                }
+                Ok(false) => println!("{cached}: up to date."),
+                Err(err) => {
+                    println!("{cached}: failed ({err:#}).");
+                    log::warn!("failed to update '{cached}': {err:#}");
+                    failed.push(cached);
+                }
+            }
+        }
+        println!();
+        if !updated.is_empty() {
+            println!("[Updated]");
+            for cached in &updated {
+                println!("│ {cached}");
+            }
+        }
+        println!(
+            "Updated {} of {} repositories.",
+            updated.len(),
+            matched.len()
+        );
+        if !failed.is_empty() {
+            bail!("failed to update {} repositories", failed.len())
+        }
+        Ok(())
+    }
+}
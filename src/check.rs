@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _, Result};
+use clap::Args;
+
+use tapgen::template::Template;
+
+use crate::config::Config;
+use crate::source::{self, Source};
+
+const BUILTINS: [&str; 5] = ["_git", "_now", "_os", "_defaults", "_template"];
+
+#[derive(Clone, Args)]
+pub(crate) struct Check {
+    #[arg(
+        help = "Source of template to be checked.",
+        value_parser = Source::from_str,
+    )]
+    src: Source,
+}
+
+impl Check {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let path = self.src.resolve(&config.prefix)?;
+        // syntax errors of templates and invalid glob patterns fail the loading
+        let template = source::load_template(&path, &config.prefix)?;
+        let problems = check(&template)?;
+        if problems.is_empty() {
+            println!("No problems found.");
+            return Ok(());
+        }
+        println!("[Problems]");
+        for problem in &problems {
+            println!("│ {problem}");
+        }
+        bail!("found {} problems in template", problems.len())
+    }
+}
+
+fn check(template: &Template) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let declared = template.variables.keys().cloned().collect::<HashSet<_>>();
+    let mut used = HashSet::new();
+
+    // variables referenced by variable conditions and previews
+    for (name, variable) in &template.variables {
+        if let Some(condition) = &variable.condition {
+            for undeclared in condition.undeclared_variables() {
+                if !declared.contains(&undeclared) && !BUILTINS.contains(&undeclared.as_str()) {
+                    problems.push(format!(
+                        "condition of variable '{name}' refers to undeclared variable '{undeclared}'"
+                    ));
+                }
+                used.insert(undeclared);
+            }
+        }
+        if let Some(preview) = &variable.preview {
+            let preview = template
+                .environment
+                .template_from_str(preview)
+                .context(format!("failed to parse preview of variable: '{name}'"))?;
+            used.extend(preview.undeclared_variables(false));
+        }
+    }
+    for (name, group) in &template.metadata.groups {
+        if let Some(condition) = &group.condition {
+            for undeclared in condition.undeclared_variables() {
+                if !declared.contains(&undeclared) && !BUILTINS.contains(&undeclared.as_str()) {
+                    problems.push(format!(
+                        "condition of group '{name}' refers to undeclared variable '{undeclared}'"
+                    ));
+                }
+                used.insert(undeclared);
+            }
+        }
+    }
+    for (pattern, condition) in template.metadata.only_if.iter() {
+        for undeclared in condition.undeclared_variables() {
+            if !declared.contains(&undeclared) && !BUILTINS.contains(&undeclared.as_str()) {
+                problems.push(format!(
+                    "condition of paths '{pattern}' refers to undeclared variable '{undeclared}'"
+                ));
+            }
+            used.insert(undeclared);
+        }
+    }
+    if let Some(locales) = &template.metadata.locales {
+        used.insert(locales.variable.clone());
+    }
+
+    // variables referenced by file names and contents, including those of the parents
+    let mut referenced = Vec::new();
+    let mut current = Some(template);
+    while let Some(template) = current {
+        for entry in template.entries.values().flatten() {
+            let name = entry.path().strip_prefix(&template.root).unwrap();
+            let name = name.to_string_lossy().to_string();
+            let path = template
+                .environment
+                .template_from_str(&name)
+                .context(format!("failed to parse path: '{name}'"))?;
+            referenced.push((name.clone(), path.undeclared_variables(false)));
+            if let Ok(file) = template.environment.get_template(&name) {
+                referenced.push((name.clone(), file.undeclared_variables(false)));
+            }
+            if let Some(condition) = template.conditions.get(&name) {
+                referenced.push((name, condition.undeclared_variables()));
+            }
+        }
+        current = template.parent.as_deref();
+    }
+    let after_hook = template.root.join("tapgen.after.hook");
+    if after_hook.exists() {
+        let source = fs::read_to_string(&after_hook).context("failed to read after hook")?;
+        let hook = template
+            .environment
+            .template_from_str(&source)
+            .context("failed to parse after hook as template")?;
+        referenced.push((
+            String::from("tapgen.after.hook"),
+            hook.undeclared_variables(false),
+        ));
+    }
+    for (name, variables) in referenced {
+        let mut undeclared = variables
+            .iter()
+            .filter(|v| !declared.contains(*v) && !BUILTINS.contains(&v.as_str()))
+            .collect::<Vec<_>>();
+        undeclared.sort();
+        for variable in undeclared {
+            problems.push(format!(
+                "'{name}' refers to undeclared variable '{variable}'"
+            ));
+        }
+        used.extend(variables);
+    }
+
+    let mut unused = declared.difference(&used).collect::<Vec<_>>();
+    unused.sort();
+    for name in unused {
+        problems.push(format!("variable '{name}' is declared but never used"));
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        let before_hook = template.root.join("tapgen.before.hook");
+        if let Ok(metadata) = fs::metadata(&before_hook) {
+            if metadata.permissions().mode() & 0o111 == 0 {
+                problems.push(String::from("'tapgen.before.hook' is not executable"));
+            }
+        }
+    }
+    Ok(problems)
+}
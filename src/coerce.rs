@@ -0,0 +1,291 @@
+use std::path::{Component, Path, PathBuf};
+
+use chrono::format::{self, ParseErrorKind, Parsed, StrftimeItems};
+use chrono::Local;
+use minijinja::{Environment, Value};
+
+use crate::utils::CoerceError;
+use crate::variable::{self, PathKind, Variable, VariableValue};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Origin {
+    CommandLine,
+    Environment,
+    AnswersFile,
+}
+
+impl std::fmt::Display for Origin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CommandLine => write!(f, "command line"),
+            Self::Environment => write!(f, "environment"),
+            Self::AnswersFile => write!(f, "answers file"),
+        }
+    }
+}
+
+fn parse_bool(input: &str) -> Option<bool> {
+    match input.trim().to_lowercase().as_str() {
+        "true" | "yes" | "y" | "on" | "1" => Some(true),
+        "false" | "no" | "n" | "off" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_array(input: &str) -> Result<Vec<String>, String> {
+    let input = input.trim();
+    if input.starts_with('[') {
+        return serde_json::from_str(input).map_err(|err| format!("malformed json array: {err}"));
+    }
+    if input.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(input
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .collect())
+}
+
+fn check_string(variable: &Variable, input: String) -> Result<Value, String> {
+    let VariableValue::String {
+        pattern,
+        choices,
+        aliases,
+        ..
+    } = &variable.value
+    else {
+        unreachable!()
+    };
+    // shorthands are normalized before validation
+    let input = match aliases.get(&input) {
+        Some(canonical) => canonical.clone(),
+        None => input,
+    };
+    if let Some(choices) = choices {
+        return match choices.iter().find(|choice| choice.value == input) {
+            Some(choice) => Ok(choice.to_value()),
+            None => Err(format!("'{input}' is not one of the choices")),
+        };
+    }
+    if let Some(pattern) = pattern {
+        if !pattern.is_match(&input) {
+            return Err(format!(
+                "'{input}' does not match pattern: `{}`",
+                pattern.as_str()
+            ));
+        }
+    }
+    Ok(Value::from(input))
+}
+
+/// Checks the number of items selected for an array variable.
+pub fn check_count(min: Option<usize>, max: Option<usize>, count: usize) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), _) if count < min => Err(format!("select at least {min} items")),
+        (_, Some(max)) if count > max => Err(format!("select at most {max} items")),
+        _ => Ok(()),
+    }
+}
+
+fn check_array(variable: &Variable, items: Vec<String>) -> Result<Value, String> {
+    let VariableValue::Array {
+        choices,
+        allow_custom,
+        min,
+        max,
+        ..
+    } = &variable.value
+    else {
+        unreachable!()
+    };
+    check_count(*min, *max, items.len())?;
+    if *allow_custom {
+        return Ok(Value::from(items));
+    }
+    if let Some(item) = items
+        .iter()
+        .find(|item| !choices.iter().any(|choice| choice.value == **item))
+    {
+        return Err(format!("'{item}' is not one of the choices"));
+    }
+    Ok(Value::from(items))
+}
+
+fn check_integer(variable: &Variable, input: i64) -> Result<Value, String> {
+    let VariableValue::Integer { range, .. } = &variable.value else {
+        unreachable!()
+    };
+    if let Some((min, max)) = range {
+        if input < *min || input > *max {
+            return Err(format!("{input} is out of range: [{min}, {max}]"));
+        }
+    }
+    Ok(Value::from(input))
+}
+
+/// Expands a leading `~` and makes `input` absolute against the current directory.
+pub fn absolute_path(input: &str) -> PathBuf {
+    let input = input.trim();
+    let path = match input.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => home::home_dir()
+            .expect("failed to locate user home directory")
+            .join(rest.trim_start_matches(['/', '\\'])),
+        _ => PathBuf::from(input),
+    };
+    let path = std::env::current_dir().unwrap_or_default().join(path);
+    // normalized lexically, as the path may not exist yet
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Resolves a path answer to an absolute path, validated against the filesystem.
+pub fn resolve_path(kind: PathKind, must_exist: bool, input: &str) -> Result<PathBuf, String> {
+    if input.trim().is_empty() {
+        return Err(String::from("path is empty"));
+    }
+    let path = absolute_path(input);
+    let is_kind = |path: &Path| match kind {
+        PathKind::File => path.is_file(),
+        PathKind::Dir => path.is_dir(),
+        PathKind::Any => true,
+    };
+    if path.exists() {
+        if !is_kind(&path) {
+            return Err(format!("'{}' is not a {kind}", path.display()));
+        }
+    } else if must_exist {
+        return Err(format!("'{}' does not exist", path.display()));
+    }
+    Ok(path)
+}
+
+/// Validates a date (or date and time) of `format`, with "today" (or "now") being formatted.
+pub fn parse_date(format: &str, input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if input.eq_ignore_ascii_case("today") || input.eq_ignore_ascii_case("now") {
+        return Ok(Local::now().format(format).to_string());
+    }
+    let mut parsed = Parsed::new();
+    format::parse(&mut parsed, input, StrftimeItems::new(format))
+        .map_err(|err| format!("'{input}' is not a date of format `{format}` ({err})"))?;
+    // formats like "%Y" (e.g. copyright years) are not complete dates, but valid nonetheless
+    match parsed.to_naive_date() {
+        Err(err) if err.kind() == ParseErrorKind::Impossible => {
+            Err(format!("'{input}' is not a valid date"))
+        }
+        _ => Ok(input.to_string()),
+    }
+}
+
+fn coerce(env: &Environment, variable: &Variable, input: &str) -> Result<Value, String> {
+    match &variable.value {
+        VariableValue::String { .. } => {
+            let input = variable
+                .transform(env, input)
+                .map_err(|err| format!("cannot transform '{input}' ({err})"))?;
+            check_string(variable, input)
+        }
+        VariableValue::Array { .. } => check_array(variable, parse_array(input)?),
+        VariableValue::Integer { .. } => {
+            let input = input
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| format!("'{input}' is not an integer"))?;
+            check_integer(variable, input)
+        }
+        VariableValue::Boolean { .. } => match parse_bool(input) {
+            Some(input) => Ok(Value::from(input)),
+            None => Err(format!("'{input}' is not a boolean")),
+        },
+        VariableValue::Path {
+            kind, must_exist, ..
+        } => {
+            resolve_path(*kind, *must_exist, input).map(|path| Value::from(path.to_string_lossy()))
+        }
+        VariableValue::Date { format, .. } => parse_date(format, input).map(Value::from),
+        VariableValue::Map { .. } => {
+            let object = serde_json::from_str::<serde_json::Value>(input)
+                .map_err(|err| format!("malformed json object: {err}"))?;
+            let object =
+                toml::Value::try_from(object).map_err(|err| format!("malformed object: {err}"))?;
+            coerce_toml(env, variable, &object)
+        }
+    }
+}
+
+fn coerce_toml(
+    env: &Environment,
+    variable: &Variable,
+    input: &toml::Value,
+) -> Result<Value, String> {
+    match (&variable.value, input) {
+        (_, toml::Value::String(input)) => coerce(env, variable, input),
+        (VariableValue::Array { .. }, toml::Value::Array(items)) => {
+            let items = items
+                .iter()
+                .map(|item| match item {
+                    toml::Value::String(item) => Ok(item.clone()),
+                    item => Err(format!("array item {item} is not a string")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            check_array(variable, items)
+        }
+        (VariableValue::Integer { .. }, toml::Value::Integer(input)) => {
+            check_integer(variable, *input)
+        }
+        (VariableValue::Boolean { .. }, toml::Value::Boolean(input)) => Ok(Value::from(*input)),
+        (VariableValue::Map { fields }, toml::Value::Table(table)) => {
+            if let Some(key) = table.keys().find(|key| !fields.contains_key(*key)) {
+                return Err(format!("'{key}' is not one of the fields"));
+            }
+            // fields left out take their defaults, as do those of false conditions
+            variable::map_value(env, fields, |name, field| match table.get(name) {
+                Some(input) => coerce_toml(env, field, input)
+                    .map_err(|reason| format!("field '{name}': {reason}")),
+                None => Ok(field.default_value(env)),
+            })
+        }
+        (_, input) => Err(format!("{input} has mismatched type")),
+    }
+}
+
+pub fn from_str(
+    env: &Environment,
+    name: &str,
+    variable: &Variable,
+    input: &str,
+    origin: Origin,
+) -> Result<Value, CoerceError> {
+    coerce(env, variable, input)
+        .and_then(|value| variable.check(env, &value).map(|_| value))
+        .map_err(|reason| CoerceError {
+            name: name.to_string(),
+            origin,
+            reason,
+        })
+}
+
+pub fn from_toml(
+    env: &Environment,
+    name: &str,
+    variable: &Variable,
+    input: &toml::Value,
+    origin: Origin,
+) -> Result<Value, CoerceError> {
+    coerce_toml(env, variable, input)
+        .and_then(|value| variable.check(env, &value).map(|_| value))
+        .map_err(|reason| CoerceError {
+            name: name.to_string(),
+            origin,
+            reason,
+        })
+}
@@ -1,41 +1,192 @@
 use std::fs;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{anyhow, Context as _, Result};
+use clap::ValueEnum;
+use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
+use toml::Table;
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub(crate) struct Config {
     pub(crate) prefix: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) log_file: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) tempdir: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) default_dst: Option<String>,
+    #[serde(default, skip_serializing_if = "Table::is_empty")]
+    pub(crate) defaults: Table,
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub(crate) aliases: IndexMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) merge_tool: Option<String>,
+    #[serde(default = "default_max_files")]
+    pub(crate) max_files: u64,
+    #[serde(default = "default_max_bytes")]
+    pub(crate) max_bytes: u64,
+    #[serde(default)]
+    pub(crate) allow_net: bool,
+    #[serde(default)]
+    pub(crate) allow_exec: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) max_cache_bytes: Option<u64>,
+    #[serde(default = "default_shallow_clone")]
+    pub(crate) shallow_clone: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) secret_command: Option<String>,
+    #[serde(default)]
+    pub(crate) hooks: HooksPolicy,
+    #[serde(default)]
+    pub(crate) apply: Apply,
+}
+
+/// Whether hook scripts of templates are run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HooksPolicy {
+    #[default]
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Apply {
+    #[serde(default)]
+    pub(crate) protect: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) file_mode: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dir_mode: Option<String>,
+}
+
+fn default_max_files() -> u64 {
+    10_000
+}
+
+fn default_max_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_shallow_clone() -> bool {
+    true
 }
 
 impl Default for Config {
     fn default() -> Self {
-        let tilde = home::home_dir().unwrap();
-        let prefix = tilde.join(".tapgen");
+        let prefix = xdg_dir("XDG_CACHE_HOME", ".cache").join("tapgen");
         Self {
             prefix: prefix.clone(),
+            log_file: None,
+            tempdir: None,
+            default_dst: None,
+            defaults: Table::new(),
+            aliases: IndexMap::new(),
+            merge_tool: None,
+            max_files: default_max_files(),
+            max_bytes: default_max_bytes(),
+            allow_net: false,
+            allow_exec: false,
+            max_cache_bytes: None,
+            shallow_clone: default_shallow_clone(),
+            secret_command: None,
+            hooks: HooksPolicy::default(),
+            apply: Apply::default(),
         }
     }
 }
 
+/// Base directory from the XDG environment variable, or its default under the home directory.
+fn xdg_dir(var: &str, default: &str) -> PathBuf {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .unwrap_or_else(|| {
+            home::home_dir()
+                .expect("failed to locate user home directory")
+                .join(default)
+        })
+}
+
 impl Config {
     pub(crate) fn init() -> Result<Self> {
-        let path = home::home_dir()
+        let mut config = match std::env::var_os("TAPGEN_CONFIG") {
+            Some(path) => {
+                let path = PathBuf::from(path);
+                let contents = fs::read_to_string(&path).context(format!(
+                    "failed to read config from TAPGEN_CONFIG: '{}'",
+                    path.display()
+                ))?;
+                toml::from_str(&contents)?
+            }
+            None => Self::load_default()?,
+        };
+        config.merge_env()?;
+        Ok(config)
+    }
+
+    /// Overrides config values by environment variables.
+    fn merge_env(&mut self) -> Result<()> {
+        if let Some(prefix) = std::env::var_os("TAPGEN_PREFIX") {
+            self.prefix = PathBuf::from(prefix);
+        }
+        if let Ok(hooks) = std::env::var("TAPGEN_HOOKS") {
+            self.hooks = HooksPolicy::from_str(&hooks, true).map_err(|_| {
+                anyhow!("invalid TAPGEN_HOOKS: '{hooks}' (expected ask, always or never)")
+            })?;
+        }
+        Ok(())
+    }
+
+    fn path() -> PathBuf {
+        xdg_dir("XDG_CONFIG_HOME", ".config")
+            .join("tapgen")
+            .join("config.toml")
+    }
+
+    fn legacy_path() -> PathBuf {
+        home::home_dir()
             .expect("failed to locate user home directory")
-            .join(".tapgen.config.toml");
+            .join(".tapgen.config.toml")
+    }
 
-        let config = if !path.exists() {
-            let config = Self::default();
-            let contents = toml::to_string_pretty(&config)?;
-            fs::write(path, contents)?;
-            config
-        } else {
-            let contents = fs::read_to_string(path)?;
-            toml::from_str(&contents)?
+    /// Reads the default config, or the legacy one until it is migrated, without writing anything.
+    fn load_default() -> Result<Self> {
+        let path = [Self::path(), Self::legacy_path()]
+            .into_iter()
+            .find(|path| path.exists());
+        let Some(path) = path else {
+            return Ok(Self::default());
         };
+        let contents = fs::read_to_string(&path)
+            .context(format!("failed to read config: '{}'", path.display()))?;
+        Ok(toml::from_str(&contents)?)
+    }
 
-        Ok(config)
+    /// Writes the default config file unless there is one, moving the legacy one there if any.
+    pub(crate) fn install() -> Result<()> {
+        let path = Self::path();
+        if std::env::var_os("TAPGEN_CONFIG").is_some() || path.exists() {
+            return Ok(());
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        let legacy = Self::legacy_path();
+        if legacy.exists() {
+            // the legacy config keeps its prefix, so nothing under it needs to be moved
+            fs::copy(&legacy, &path)?;
+            fs::remove_file(&legacy)?;
+            say!(
+                "Migrated config from '{}' to '{}'.",
+                legacy.display(),
+                path.display()
+            );
+        } else {
+            fs::write(&path, toml::to_string_pretty(&Self::default())?)?;
+        }
+        Ok(())
     }
 }
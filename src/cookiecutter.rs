@@ -0,0 +1,163 @@
+use std::borrow::Cow;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use glob::Pattern;
+use regex::{Captures, Regex};
+use serde_json::{Map, Value as Json};
+use toml::{Table, Value};
+
+use crate::utils::{Error, Result};
+
+pub(crate) const CONTEXT_FILE: &str = "cookiecutter.json";
+
+/// Translates the Jinja tags of a cookiecutter template to those of tapgen: answers are referred to
+/// directly instead of through `cookiecutter`, and common string methods become filters.
+pub(crate) fn translate(source: &str) -> Cow<str> {
+    static TAG: OnceLock<Regex> = OnceLock::new();
+    static CONTEXT: OnceLock<Regex> = OnceLock::new();
+    static METHOD: OnceLock<Regex> = OnceLock::new();
+    let tag = TAG.get_or_init(|| Regex::new(r"(?s)\{\{.*?\}\}|\{%.*?%\}").unwrap());
+    let context = CONTEXT.get_or_init(|| Regex::new(r"\bcookiecutter\.").unwrap());
+    let method = METHOD.get_or_init(|| {
+        Regex::new(r"\.(?:(?<filter>lower|upper|title|capitalize)\(\)|(?<strip>strip)\(\)|(?<replace>replace)\()")
+            .unwrap()
+    });
+    tag.replace_all(source, |captures: &Captures| {
+        let tag = context.replace_all(&captures[0], "");
+        method
+            .replace_all(&tag, |captures: &Captures| {
+                if let Some(filter) = captures.name("filter") {
+                    format!("|{}", filter.as_str())
+                } else if captures.name("strip").is_some() {
+                    String::from("|trim")
+                } else {
+                    String::from("|replace(")
+                }
+            })
+            .into_owned()
+    })
+}
+
+/// Variable of a cookiecutter context entry, with its prompt (and labels of choices) in `__prompts__`.
+fn variable(name: &str, value: &Json, prompt: Option<&Json>) -> Result<Table> {
+    let (prompt, labels) = match prompt {
+        Some(Json::String(prompt)) => (prompt.as_str(), None),
+        Some(Json::Object(prompts)) => (
+            prompts
+                .get("__prompt__")
+                .and_then(Json::as_str)
+                .unwrap_or(name),
+            Some(prompts),
+        ),
+        _ => (name, None),
+    };
+    let mut variable = Table::new();
+    variable.insert(String::from("prompt"), Value::from(prompt));
+    match value {
+        Json::String(default) => {
+            variable.insert(
+                String::from("default"),
+                Value::from(translate(default).into_owned()),
+            );
+        }
+        Json::Bool(default) => {
+            variable.insert(String::from("default"), Value::from(*default));
+        }
+        Json::Number(default) => {
+            let default = match default.as_i64() {
+                Some(default) => Value::from(default),
+                None => Value::from(default.to_string()),
+            };
+            variable.insert(String::from("default"), default);
+        }
+        // choice variables default to their first choice
+        Json::Array(choices) => {
+            let choices = choices
+                .iter()
+                .map(|choice| match choice {
+                    Json::String(choice) => choice.clone(),
+                    choice => choice.to_string(),
+                })
+                .collect::<Vec<_>>();
+            let Some(default) = choices.first() else {
+                return Err(Error::Cookiecutter(format!("'{name}' has no choices")));
+            };
+            variable.insert(String::from("default"), Value::from(default.as_str()));
+            let choices = choices
+                .iter()
+                .map(
+                    |choice| match labels.and_then(|labels| labels.get(choice)?.as_str()) {
+                        Some(label) => Value::Table(Table::from_iter([
+                            (String::from("value"), Value::from(choice.as_str())),
+                            (String::from("label"), Value::from(label)),
+                        ])),
+                        None => Value::from(choice.as_str()),
+                    },
+                )
+                .collect::<Vec<_>>();
+            variable.insert(String::from("choices"), Value::Array(choices));
+        }
+        Json::Object(fields) => {
+            let fields = fields
+                .iter()
+                .map(|(field, value)| {
+                    Ok((field.clone(), Value::Table(variable(field, value, None)?)))
+                })
+                .collect::<Result<Table>>()?;
+            variable.insert(String::from("fields"), Value::Table(fields));
+        }
+        Json::Null => return Err(Error::Cookiecutter(format!("'{name}' is null"))),
+    }
+    Ok(variable)
+}
+
+/// Converts the `cookiecutter.json` of a cookiecutter template at `root` to a definition,
+/// whose base is the templated directory (e.g. `{{cookiecutter.project_slug}}`).
+pub(crate) fn to_definition(root: &Path, json: &str) -> Result<Table> {
+    let context = serde_json::from_str::<Map<String, Json>>(json)
+        .map_err(|err| Error::Cookiecutter(err.to_string()))?;
+    let mut base = None;
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() && name.contains("{{") && name.contains("cookiecutter") {
+            base = Some(name);
+            break;
+        }
+    }
+    let Some(base) = base else {
+        return Err(Error::Cookiecutter(String::from(
+            "no templated directory, e.g. '{{cookiecutter.project_slug}}'",
+        )));
+    };
+    let name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let mut definition = Table::new();
+    definition.insert(String::from("__name__"), Value::from(name));
+    definition.insert(String::from("__author__"), Value::from(""));
+    // patterns of `_copy_without_render` are relative to the templated directory
+    if let Some(Json::Array(patterns)) = context.get("_copy_without_render") {
+        let patterns = patterns
+            .iter()
+            .filter_map(Json::as_str)
+            .map(|pattern| Value::from(format!("{}/{pattern}", Pattern::escape(&base))))
+            .collect();
+        definition.insert(String::from("__copy__"), Value::Array(patterns));
+    }
+    definition.insert(String::from("__base__"), Value::from(base));
+    let prompts = context.get("__prompts__").and_then(Json::as_object);
+    for (name, value) in &context {
+        // private entries (e.g. `_extensions`, `__prompts__`) are not asked
+        if name.starts_with('_') {
+            continue;
+        }
+        let prompt = prompts.and_then(|prompts| prompts.get(name));
+        definition.insert(name.clone(), Value::Table(variable(name, value, prompt)?));
+    }
+    Ok(definition)
+}
@@ -0,0 +1,246 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use glob::Pattern;
+use regex::Regex;
+use serde_yaml::{Mapping, Value as Yaml};
+use toml::{Table, Value};
+
+use crate::utils::{Error, Result};
+
+pub(crate) const CONFIG_FILES: [&str; 2] = ["copier.yml", "copier.yaml"];
+
+/// Excluded by copier unless `_exclude` is given.
+const DEFAULT_EXCLUDE: &[&str] = &["~*", "*.py[co]", "__pycache__", ".git", ".DS_Store", ".svn"];
+
+const DEFAULT_SUFFIX: &str = ".jinja";
+
+/// Keys of a question, as opposed to a default given in short form.
+const QUESTION_KEYS: &[&str] = &[
+    "type",
+    "help",
+    "default",
+    "choices",
+    "multiselect",
+    "when",
+    "validator",
+    "secret",
+    "placeholder",
+];
+
+fn invalid(name: &str, reason: impl std::fmt::Display) -> Error {
+    Error::Copier(format!("question '{name}': {reason}"))
+}
+
+/// Scalar as a string, e.g. a choice of a number.
+fn scalar(value: &Yaml) -> Option<String> {
+    match value {
+        Yaml::String(value) => Some(value.clone()),
+        Yaml::Number(value) => Some(value.to_string()),
+        Yaml::Bool(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Condition of `when`, being either a boolean or a template of a single expression.
+fn condition(name: &str, when: &Yaml) -> Result<String> {
+    static EXPRESSION: OnceLock<Regex> = OnceLock::new();
+    let expression =
+        EXPRESSION.get_or_init(|| Regex::new(r"(?s)^\s*\{\{(?<expression>.*)\}\}\s*$").unwrap());
+    match when {
+        Yaml::Bool(when) => Ok(when.to_string()),
+        Yaml::String(when) => match expression.captures(when) {
+            Some(captures) => Ok(captures["expression"].trim().to_string()),
+            None => Err(invalid(name, format!("unsupported condition: '{when}'"))),
+        },
+        _ => Err(invalid(name, "condition is neither a boolean nor a string")),
+    }
+}
+
+/// Choices of a list (of values, or of label and value pairs) or a mapping of labels to values.
+fn choices(name: &str, choices: &Yaml) -> Result<Vec<Value>> {
+    let choice = |label: Option<&Yaml>, value: &Yaml| -> Result<Value> {
+        let value = scalar(value).ok_or_else(|| invalid(name, "choice is not a scalar"))?;
+        Ok(match label.and_then(scalar) {
+            Some(label) => Value::Table(Table::from_iter([
+                (String::from("value"), Value::from(value)),
+                (String::from("label"), Value::from(label)),
+            ])),
+            None => Value::from(value),
+        })
+    };
+    match choices {
+        Yaml::Sequence(choices) => choices
+            .iter()
+            .map(|item| match item {
+                Yaml::Sequence(pair) if pair.len() == 2 => choice(Some(&pair[0]), &pair[1]),
+                value => choice(None, value),
+            })
+            .collect(),
+        Yaml::Mapping(choices) => choices
+            .iter()
+            .map(|(label, value)| choice(Some(label), value))
+            .collect(),
+        _ => Err(invalid(name, "choices are neither a list nor a mapping")),
+    }
+}
+
+/// Variable of a copier question, or of a default given in short form (e.g. `name: foo`).
+fn variable(name: &str, question: &Yaml) -> Result<Table> {
+    let question = match question {
+        Yaml::Mapping(question)
+            if question
+                .keys()
+                .any(|key| key.as_str().is_some_and(|key| QUESTION_KEYS.contains(&key))) =>
+        {
+            question.clone()
+        }
+        default => Mapping::from_iter([(Yaml::from("default"), default.clone())]),
+    };
+    let get = |key: &str| question.get(key).filter(|value| !value.is_null());
+    let mut variable = Table::new();
+    // the prompt is the first line of the help, as copier shows it above the question
+    let prompt = get("help")
+        .and_then(Yaml::as_str)
+        .and_then(|help| help.lines().next())
+        .unwrap_or(name);
+    variable.insert(String::from("prompt"), Value::from(prompt));
+    if let Some(when) = get("when") {
+        variable.insert(
+            String::from("condition"),
+            Value::from(condition(name, when)?),
+        );
+    }
+    if get("validator").is_some() {
+        log::warn!("validator of question '{name}' is not supported");
+    }
+    let default = get("default");
+    let kind = match get("type").and_then(Yaml::as_str) {
+        Some(kind) => kind,
+        None => match default {
+            Some(Yaml::Bool(_)) => "bool",
+            Some(Yaml::Number(number)) if number.is_i64() => "int",
+            _ => "str",
+        },
+    };
+    if let Some(items) = get("choices") {
+        let choices = choices(name, items)?;
+        let values = choices
+            .iter()
+            .map(|choice| match choice {
+                Value::Table(choice) => choice["value"].as_str().unwrap().to_string(),
+                choice => choice.as_str().unwrap().to_string(),
+            })
+            .collect::<Vec<_>>();
+        if get("multiselect").and_then(Yaml::as_bool) == Some(true) {
+            let default = match default {
+                Some(Yaml::Sequence(items)) => items.iter().filter_map(scalar).collect(),
+                Some(item) => scalar(item).into_iter().collect(),
+                None => Vec::new(),
+            };
+            variable.insert(String::from("default"), Value::from(default));
+        } else {
+            // copier asks for a choice without a default
+            let default = default
+                .and_then(scalar)
+                .or_else(|| values.first().cloned())
+                .ok_or_else(|| invalid(name, "no choices"))?;
+            variable.insert(String::from("default"), Value::from(default));
+        }
+        variable.insert(String::from("choices"), Value::Array(choices));
+        return Ok(variable);
+    }
+    let default = match (kind, default) {
+        ("bool", Some(Yaml::Bool(default))) => Value::from(*default),
+        ("bool", None) => Value::from(false),
+        ("int", Some(Yaml::Number(default))) => match default.as_i64() {
+            Some(default) => Value::from(default),
+            None => return Err(invalid(name, "default is not an integer")),
+        },
+        ("int", None) => Value::from(0),
+        ("bool" | "int", Some(_)) => return Err(invalid(name, "default has mismatched type")),
+        // floats, json, yaml and paths are asked as strings
+        ("str" | "float" | "json" | "yaml" | "path", default) => {
+            Value::from(default.and_then(scalar).unwrap_or_default())
+        }
+        (kind, _) => return Err(invalid(name, format!("unsupported type: '{kind}'"))),
+    };
+    if default.is_str() && get("secret").and_then(Yaml::as_bool) == Some(true) {
+        variable.insert(String::from("secret"), Value::from(true));
+    }
+    variable.insert(String::from("default"), default);
+    Ok(variable)
+}
+
+/// Glob patterns of a gitignore-like pattern of `_exclude`, matching paths at any depth
+/// along with their contents.
+fn exclude_patterns(prefix: &str, pattern: &str) -> Vec<String> {
+    let pattern = pattern.trim_start_matches('/').trim_end_matches('/');
+    vec![
+        format!("{prefix}{pattern}"),
+        format!("{prefix}{pattern}/*"),
+        format!("{prefix}*/{pattern}"),
+        format!("{prefix}*/{pattern}/*"),
+    ]
+}
+
+/// Converts the `copier.yml` of a copier template at `root` to a definition,
+/// whose base is its `_subdirectory` (or the root itself).
+pub(crate) fn to_definition(root: &Path, yaml: &str) -> Result<Table> {
+    let config =
+        serde_yaml::from_str::<Mapping>(yaml).map_err(|err| Error::Copier(err.to_string()))?;
+    let setting = |key: &str| config.get(key).filter(|value| !value.is_null());
+    let name = root
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let base = setting("_subdirectory")
+        .and_then(Yaml::as_str)
+        .unwrap_or(".")
+        .trim_matches('/');
+    let base = if base.is_empty() { "." } else { base };
+
+    let mut definition = Table::new();
+    definition.insert(String::from("__name__"), Value::from(name));
+    definition.insert(String::from("__author__"), Value::from(""));
+    definition.insert(String::from("__base__"), Value::from(base));
+    let suffix = match setting("_templates_suffix") {
+        Some(suffix) => suffix.as_str().unwrap_or_default(),
+        None => DEFAULT_SUFFIX,
+    };
+    if !suffix.is_empty() {
+        definition.insert(String::from("__templates_suffix__"), Value::from(suffix));
+    }
+    // patterns of `_exclude` are relative to the subdirectory
+    let prefix = match base {
+        "." => String::new(),
+        base => format!("{}/", Pattern::escape(base)),
+    };
+    // the config file itself is never emitted
+    let exclude: Vec<String> = match setting("_exclude") {
+        Some(Yaml::Sequence(patterns)) => patterns.iter().filter_map(scalar).collect(),
+        Some(_) => return Err(Error::Copier(String::from("_exclude is not a list"))),
+        None => DEFAULT_EXCLUDE.iter().map(|p| p.to_string()).collect(),
+    };
+    let exclude = exclude
+        .iter()
+        .map(String::as_str)
+        .chain(CONFIG_FILES)
+        .flat_map(|pattern| exclude_patterns(&prefix, pattern))
+        .map(Value::from)
+        .collect();
+    definition.insert(String::from("__exclude__"), Value::Array(exclude));
+    for (name, question) in &config {
+        let Some(name) = name.as_str() else {
+            return Err(Error::Copier(format!(
+                "question {name:?} is not named by a string"
+            )));
+        };
+        // settings of copier itself
+        if name.starts_with('_') {
+            continue;
+        }
+        definition.insert(name.to_string(), Value::Table(variable(name, question)?));
+    }
+    Ok(definition)
+}
@@ -1,51 +1,365 @@
-use std::{fs, path::Path};
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{Context as _, Result};
+use anyhow::{bail, Context as _, Result};
+use clap::ValueEnum;
+use glob::Pattern;
+use serde_json::json;
+use tapgen::metadata::Modes;
+use tapgen::template;
+use tempfile::NamedTempFile;
+use walkdir::WalkDir;
 
-use crate::prompt;
+use crate::diff::{self, Change};
+use crate::journal::Journal;
+use crate::{merge, porcelain, prompt, shell};
+
+/// Directory under the destination of backups made with `--backup dir`.
+pub(crate) const BACKUP_DIR: &str = ".tapgen-backup";
+
+/// Where files are backed up before being overwritten.
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum BackupMode {
+    /// `<name>.bak` next to the file
+    Suffix,
+    /// `.tapgen-backup/<timestamp>/<name>` under the destination
+    Dir,
+}
+
+pub(crate) enum Backup {
+    Suffix,
+    Dir(PathBuf), // of this apply, e.g. `.tapgen-backup/20240101120000`
+}
+
+pub(crate) struct Options {
+    pub(crate) force: bool,
+    pub(crate) merge_tool: Option<String>,
+    pub(crate) merge: bool, // marking conflicts in existing files instead of overwriting them
+    pub(crate) backup: Option<Backup>,
+    pub(crate) journal: RefCell<Journal>,
+    pub(crate) protect: Vec<Pattern>,
+    pub(crate) interactive: bool,
+    pub(crate) modes: Modes,
+    pub(crate) file_mode: Option<u32>,
+    pub(crate) dir_mode: Option<u32>,
+}
+
+#[cfg(unix)]
+fn umask() -> u32 {
+    // umask can only be read by setting it, so restore it right away
+    unsafe {
+        let mask = libc::umask(0);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+/// Applies the template or configured mode of `name` at `to`, masked by the umask.
+fn set_mode(name: &Path, to: &Path, default: Option<u32>, options: &Options) -> Result<()> {
+    // the modes are of paths relative to the output directory, whose name comes first
+    let mut relative = name.components();
+    relative.next();
+    let Some(mode) = options.modes.mode(relative.as_path()).or(default) else {
+        return Ok(());
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        let mode = mode & !umask();
+        log::debug!("chmod {mode:o} '{}'", to.display());
+        fs::set_permissions(to, fs::Permissions::from_mode(mode))
+            .context(format!("failed to set mode of '{}'", to.display()))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (mode, to);
+    Ok(())
+}
+
+/// `mode` with the executable bits of its readable classes set if the file at `path` is executable,
+/// so that configured modes do not strip them from scripts.
+fn with_executable(mode: u32, path: &Path) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        match fs::metadata(path) {
+            Ok(metadata) if metadata.permissions().mode() & 0o111 != 0 => {
+                mode | (mode & 0o444) >> 2
+            }
+            _ => mode,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        mode
+    }
+}
+
+/// Applies the modes to the paths under `path` moved into the destination as they are.
+pub(crate) fn apply_modes(dstroot: &Path, path: &Path, options: &Options) -> Result<()> {
+    for entry in WalkDir::new(path) {
+        let entry = entry?;
+        let name = entry.path().strip_prefix(dstroot).unwrap();
+        if entry.file_type().is_dir() {
+            set_mode(name, entry.path(), options.dir_mode, options)?;
+        } else if entry.file_type().is_file() {
+            let file_mode = options
+                .file_mode
+                .map(|mode| with_executable(mode, entry.path()));
+            set_mode(name, entry.path(), file_mode, options)?;
+        }
+    }
+    Ok(())
+}
+
+enum Resolution {
+    Overwrite,
+    Skip,
+    Merge,
+    Markers,
+}
+
+fn resolve_conflict(
+    name: &Path,
+    generated: &Path,
+    existing: &Path,
+    options: &Options,
+) -> Resolution {
+    if options.force {
+        return Resolution::Overwrite;
+    }
+    if options.merge {
+        return Resolution::Markers;
+    }
+    if !options.interactive {
+        return Resolution::Skip;
+    }
+    let mut items = vec!["Overwrite", "Skip"];
+    if options.merge_tool.is_some() {
+        items.push("Merge");
+    }
+    items.push("Diff");
+    loop {
+        match prompt::select(format!("Conflict at '{}'", name.display()), &items, None) {
+            "Overwrite" => return Resolution::Overwrite,
+            "Merge" => return Resolution::Merge,
+            "Diff" => match diff::compare(generated, existing, &name.to_string_lossy()) {
+                Change::Modified(Some(diff)) => say!("{}", diff.trim_end()),
+                Change::Modified(None) => say!("Binary files differ."),
+                _ => say!("Files are identical."),
+            },
+            _ => return Resolution::Skip,
+        }
+    }
+}
+
+fn merge_file(tool: &str, generated: &Path, to: &Path) -> Result<()> {
+    // there is no previously generated version to compare against, so the base is empty
+    let base =
+        NamedTempFile::with_prefix("tapgen-base-").context("failed to create temporary file")?;
+    let local =
+        NamedTempFile::with_prefix("tapgen-local-").context("failed to create temporary file")?;
+    fs::copy(to, local.path()).context(format!("failed to copy file: '{}'", to.display()))?;
+    let command = tool
+        .replace("$BASE", &shell::quote(base.path()))
+        .replace("$LOCAL", &shell::quote(local.path()))
+        .replace("$REMOTE", &shell::quote(generated))
+        .replace("$MERGED", &shell::quote(to));
+    log::info!("running merge tool: {command}");
+    let status = shell::command(&command)
+        .status()
+        .context("failed to run merge tool")?;
+    if !status.success() {
+        bail!("merge tool failed with {status}")
+    }
+    Ok(())
+}
+
+/// Copies the existing file at `to` to its backup before it is overwritten.
+fn back_up(name: &Path, to: &Path, options: &Options) -> Result<()> {
+    let path = match &options.backup {
+        None => return Ok(()),
+        Some(Backup::Suffix) => {
+            let mut path = to.as_os_str().to_owned();
+            path.push(".bak");
+            PathBuf::from(path)
+        }
+        Some(Backup::Dir(dir)) => dir.join(name),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    log::debug!("back up '{}' -> '{}'", to.display(), path.display());
+    fs::copy(to, &path).context(format!("failed to back up file: '{}'", name.display()))?;
+    Ok(())
+}
+
+/// Writes the generated file into the existing one with conflict markers where they differ,
+/// returning the number of conflicts, or `None` if either is binary.
+fn mark_conflicts(
+    name: &Path,
+    generated: &Path,
+    to: &Path,
+    options: &Options,
+) -> Result<Option<usize>> {
+    let (Ok(generated), Ok(existing)) = (
+        String::from_utf8(fs::read(generated)?),
+        String::from_utf8(fs::read(to)?),
+    ) else {
+        return Ok(None);
+    };
+    let (merged, conflicts) = merge::merge2(&existing, &generated, ("existing", "generated"));
+    if conflicts > 0 {
+        back_up(name, to, options)?;
+        options.journal.borrow_mut().overwriting(name)?;
+        fs::write(to, merged)?;
+    }
+    Ok(Some(conflicts))
+}
+
+fn emit_apply(name: &Path, action: &str) {
+    porcelain::emit("apply", json!({ "path": name, "action": action }));
+}
 
 pub(crate) fn copy_dir_all(
     dstroot: impl AsRef<Path>,
     src: impl AsRef<Path>,
     dst: impl AsRef<Path>,
-    force: bool,
+    options: &Options,
 ) -> Result<(u32, u32, u32)> {
     let (mut creates, mut overwrites, mut skips) = (0, 0, 0);
+    let created = !dst.as_ref().exists();
     fs::create_dir_all(&dst).context(format!(
         "failed to create destination directory: '{}'",
         dst.as_ref().display()
     ))?;
+    if created {
+        let name = dst.as_ref().strip_prefix(dstroot.as_ref()).unwrap();
+        options.journal.borrow_mut().created_dir(name);
+        set_mode(name, dst.as_ref(), options.dir_mode, options)?;
+    }
     for entry in fs::read_dir(&src).context(format!(
         "failed to read source directory: '{}'",
         src.as_ref().display()
     ))? {
         let entry = entry.unwrap();
         let to = dst.as_ref().join(entry.file_name());
-        if entry.file_type().unwrap().is_dir() {
-            let (c, o, s) = copy_dir_all(dstroot.as_ref(), entry.path(), to, force)?;
+        let file_type = entry.file_type().unwrap();
+        if file_type.is_dir() {
+            let (c, o, s) = copy_dir_all(dstroot.as_ref(), entry.path(), to, options)?;
             creates += c;
             overwrites += o;
             skips += s;
+        } else if file_type.is_symlink() {
+            let name = to.strip_prefix(dstroot.as_ref()).unwrap();
+            let target = fs::read_link(entry.path())?;
+            if !template::is_link_contained(name, &target) {
+                say!(
+                    "Skipped symlink pointing outside of the destination: '{}'",
+                    name.display()
+                );
+                log::debug!(
+                    "skip '{}' (symlink to '{}')",
+                    to.display(),
+                    target.display()
+                );
+                emit_apply(name, "skip");
+                skips += 1;
+                continue;
+            }
+            // whatever is at the path is kept, as a symlink cannot be merged into it
+            if to.symlink_metadata().is_ok() {
+                if fs::read_link(&to).is_ok_and(|existing| existing == target) {
+                    log::debug!("unchanged '{}'", to.display());
+                } else {
+                    say!("Skipped symlink over existing path: '{}'", name.display());
+                    log::debug!("skip '{}' (symlink)", to.display());
+                }
+                emit_apply(name, "skip");
+                skips += 1;
+                continue;
+            }
+            log::debug!("link '{}' -> '{}'", to.display(), target.display());
+            emit_apply(name, "create");
+            options.journal.borrow_mut().created(name);
+            template::symlink(name, &target, &to, entry.path().is_dir())
+                .context(format!("failed to create symlink: '{}'", name.display()))?;
+            creates += 1;
         } else {
             if to.exists() {
-                if force
-                    || prompt::confirm(
-                        format!(
-                            "Overwrite '{}'?",
-                            to.strip_prefix(dstroot.as_ref()).unwrap().display()
-                        ),
-                        None,
-                    )
-                {
-                    overwrites += 1;
-                } else {
+                let name = to.strip_prefix(dstroot.as_ref()).unwrap();
+                if options.protect.iter().any(|p| p.matches_path(name)) {
+                    say!("Skipped protected file: '{}'", name.display());
+                    log::debug!("protect '{}'", to.display());
+                    emit_apply(name, "protect");
                     skips += 1;
+                    continue;
+                }
+                match resolve_conflict(name, &entry.path(), &to, options) {
+                    Resolution::Overwrite => {
+                        back_up(name, &to, options)?;
+                        options.journal.borrow_mut().overwriting(name)?;
+                        log::debug!("overwrite '{}'", to.display());
+                        emit_apply(name, "overwrite");
+                        overwrites += 1;
+                    }
+                    Resolution::Skip => {
+                        log::debug!("skip '{}'", to.display());
+                        emit_apply(name, "skip");
+                        skips += 1;
+                        continue;
+                    }
+                    Resolution::Merge => {
+                        log::debug!("merge '{}'", to.display());
+                        emit_apply(name, "merge");
+                        let tool = options.merge_tool.as_deref().unwrap();
+                        back_up(name, &to, options)?;
+                        options.journal.borrow_mut().overwriting(name)?;
+                        merge_file(tool, &entry.path(), &to)
+                            .context(format!("failed to merge file: '{}'", name.display()))?;
+                        overwrites += 1;
+                        continue;
+                    }
+                    Resolution::Markers => {
+                        let conflicts = mark_conflicts(name, &entry.path(), &to, options)
+                            .context(format!("failed to merge file: '{}'", name.display()))?;
+                        match conflicts {
+                            Some(0) => {
+                                log::debug!("unchanged '{}'", to.display());
+                                emit_apply(name, "skip");
+                                skips += 1;
+                            }
+                            Some(conflicts) => {
+                                say!("Marked {conflicts} conflicts in '{}'", name.display());
+                                log::debug!("mark '{}'", to.display());
+                                emit_apply(name, "mark");
+                                overwrites += 1;
+                            }
+                            None => {
+                                say!("Skipped binary file: '{}'", name.display());
+                                log::debug!("skip '{}' (binary)", to.display());
+                                emit_apply(name, "skip");
+                                skips += 1;
+                            }
+                        }
+                        continue;
+                    }
                 }
             } else {
+                let name = to.strip_prefix(dstroot.as_ref()).unwrap();
+                log::debug!("create '{}'", to.display());
+                emit_apply(name, "create");
+                options.journal.borrow_mut().created(name);
                 creates += 1;
             }
-            fs::copy(entry.path(), to)
+            fs::copy(entry.path(), &to)
                 .context(format!("failed to copy file: '{}'", entry.path().display()))?;
+            let name = to.strip_prefix(dstroot.as_ref()).unwrap();
+            let file_mode = options
+                .file_mode
+                .map(|mode| with_executable(mode, &entry.path()));
+            set_mode(name, &to, file_mode, options)?;
         }
     }
     Ok((creates, overwrites, skips))
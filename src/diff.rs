@@ -0,0 +1,62 @@
+use std::fs;
+use std::path::Path;
+
+use similar::TextDiff;
+use walkdir::WalkDir;
+
+pub(crate) enum Change {
+    Created,
+    Modified(Option<String>), // unified diff, if both are text
+    Unchanged,
+}
+
+/// Compares the generated file with the existing file at the destination.
+pub(crate) fn compare(generated: &Path, existing: &Path, name: &str) -> Change {
+    if !existing.exists() {
+        return Change::Created;
+    }
+    let (Ok(new), Ok(old)) = (fs::read(generated), fs::read(existing)) else {
+        return Change::Modified(None);
+    };
+    if new == old {
+        return Change::Unchanged;
+    }
+    match (String::from_utf8(old), String::from_utf8(new)) {
+        (Ok(old), Ok(new)) => Change::Modified(Some(
+            TextDiff::from_lines(&old, &new)
+                .unified_diff()
+                .header(&format!("a/{name}"), &format!("b/{name}"))
+                .to_string(),
+        )),
+        _ => Change::Modified(None),
+    }
+}
+
+/// Prints the changes that applying `src` onto `dst` would make.
+pub(crate) fn print_changes(src: &Path, dst: &Path) {
+    let (mut created, mut modified, mut unchanged) = (0, 0, 0);
+    for entry in WalkDir::new(src)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let relative = entry.path().strip_prefix(src).unwrap();
+        let name = relative.to_string_lossy().replace('\\', "/");
+        match compare(entry.path(), &dst.join(relative), &name) {
+            Change::Created => {
+                created += 1;
+                say!("created: {name}");
+            }
+            Change::Modified(diff) => {
+                modified += 1;
+                match diff {
+                    Some(diff) => say!("{}", diff.trim_end()),
+                    None => say!("modified: {name} (binary)"),
+                }
+            }
+            Change::Unchanged => unchanged += 1,
+        }
+    }
+    say!("{created} created, {modified} modified, {unchanged} unchanged.");
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use indexmap::IndexMap;
+use minijinja::value::Rest;
+use minijinja::{Environment, Error, ErrorKind, State, Value};
+
+use crate::metadata::CustomFunction;
+use crate::utils;
+
+pub(crate) fn scoped_path(root: &Path, path: &str) -> Result<PathBuf, Error> {
+    let resolved = root.join(path).canonicalize().map_err(|err| {
+        Error::new(
+            ErrorKind::InvalidOperation,
+            format!("cannot resolve path: '{path}'"),
+        )
+        .with_source(err)
+    })?;
+    if !resolved.starts_with(root) {
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!("path outside template root: '{path}'"),
+        ));
+    }
+    Ok(resolved)
+}
+
+pub(crate) fn register(environment: &mut Environment<'static>, root: &Path) {
+    {
+        let root = root.to_path_buf();
+        environment.add_function("read_file", move |path: String| {
+            let path = scoped_path(&root, &path)?;
+            fs::read_to_string(&path).map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot read file: '{}'", path.display()),
+                )
+                .with_source(err)
+            })
+        });
+    }
+    {
+        let root = root.to_path_buf();
+        environment.add_function("glob", move |pattern: String| {
+            if Path::new(&pattern)
+                .components()
+                .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+            {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("pattern outside template root: '{pattern}'"),
+                ));
+            }
+            let pattern = utils::path_to_string(root.join(&pattern));
+            let paths = glob::glob(&pattern).map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("invalid glob pattern: '{pattern}'"),
+                )
+                .with_source(err)
+            })?;
+            let mut matches = paths
+                .filter_map(Result::ok)
+                .filter_map(|path| path.strip_prefix(&root).ok().map(utils::path_to_string))
+                .collect::<Vec<_>>();
+            matches.sort();
+            Ok(matches)
+        });
+    }
+}
+
+/// Registers the functions defined by the template, except those backed by commands,
+/// which are left to the caller to register where command execution is allowed.
+pub(crate) fn register_custom(
+    environment: &mut Environment<'static>,
+    root: &Path,
+    functions: &IndexMap<String, CustomFunction>,
+) -> Result<(), Error> {
+    for (name, function) in functions {
+        match function {
+            CustomFunction::Command { .. } => {}
+            CustomFunction::Expression { expression, args } => {
+                // compiled eagerly to report syntax errors on load
+                environment.compile_expression(expression)?;
+                let (name, expression, params) = (name.clone(), expression.clone(), args.clone());
+                environment.add_function(name.clone(), move |state: &State, args: Rest<Value>| {
+                    if args.len() > params.len() {
+                        return Err(Error::new(
+                            ErrorKind::TooManyArguments,
+                            format!("function '{name}' takes {} arguments", params.len()),
+                        ));
+                    }
+                    let ctx = params
+                        .iter()
+                        .zip(
+                            args.iter()
+                                .cloned()
+                                .chain(std::iter::repeat(Value::UNDEFINED)),
+                        )
+                        .map(|(param, arg)| (param.as_str(), arg))
+                        .collect::<HashMap<_, _>>();
+                    state.env().compile_expression(&expression)?.eval(ctx)
+                });
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Result};
+use clap::Args;
+use minijinja::{Environment, Value};
+
+use tapgen::template::Template;
+use tapgen::variable::{self, Variable, VariableValue};
+
+use crate::config::Config;
+use crate::generate::{builtin_values, lint_output, resolve_variables};
+use crate::source::{self, Allow, Source};
+
+const DEFAULT_RUNS: usize = 100;
+
+/// Candidates tried against patterns of string variables without choices.
+const STRINGS: &[&str] = &[
+    "foo", "foo-bar", "foo_bar", "FooBar", "x", "a1", "foo.bar", "Foo Bar", "0.1.0", "",
+];
+
+/// Xorshift generator, enough for picking answers reproducibly from a seed.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+
+    fn bool(&mut self) -> bool {
+        self.next() & 1 == 1
+    }
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Fuzz {
+    #[arg(
+        help = "Source of template to be fuzzed.",
+        value_parser = Source::from_str,
+    )]
+    src: Source,
+    #[command(flatten)]
+    allow: Allow,
+    #[arg(
+        short = 'n',
+        long = "runs",
+        value_name = "N",
+        default_value_t = DEFAULT_RUNS,
+        help = "Number of random answer sets to generate with."
+    )]
+    runs: usize,
+    #[arg(
+        long = "seed",
+        help = "Seed of the random answers, for reproducing a run."
+    )]
+    seed: Option<u64>,
+}
+
+/// Random answer of the variable that passes its validation.
+fn random_value(env: &Environment, rng: &mut Rng, variable: &Variable) -> Value {
+    match &variable.value {
+        VariableValue::String {
+            choices: Some(choices),
+            ..
+        } => choices[rng.below(choices.len())].to_value(),
+        VariableValue::String {
+            default, pattern, ..
+        } => {
+            let mut candidates = STRINGS
+                .iter()
+                .map(|s| s.to_string())
+                .chain([default.clone(), format!("{default}{}", rng.below(100))])
+                .filter(|s| pattern.as_ref().map_or(true, |p| p.is_match(s)))
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                candidates.push(default.clone());
+            }
+            Value::from(candidates.swap_remove(rng.below(candidates.len())))
+        }
+        VariableValue::Array {
+            choices, min, max, ..
+        } => {
+            let (selected, rest): (Vec<_>, Vec<_>) = choices.iter().partition(|_| rng.bool());
+            // topped up or cut down to the allowed number of items
+            let count = selected
+                .len()
+                .clamp(min.unwrap_or(0), max.unwrap_or(usize::MAX));
+            Value::from(
+                selected
+                    .into_iter()
+                    .chain(rest)
+                    .take(count)
+                    .map(|choice| choice.value.clone())
+                    .collect::<Vec<_>>(),
+            )
+        }
+        VariableValue::Integer { default, range } => {
+            let (min, max) =
+                range.unwrap_or((default.saturating_sub(10), default.saturating_add(10)));
+            let span = max.abs_diff(min).saturating_add(1);
+            Value::from(min.saturating_add((rng.next() % span.max(1)) as i64))
+        }
+        VariableValue::Boolean { .. } => Value::from(rng.bool()),
+        // the filesystem is not fuzzed
+        VariableValue::Path { .. } | VariableValue::Date { .. } => variable.default_value(env),
+        VariableValue::Map { fields } => variable::map_value(env, fields, |_, field| {
+            Ok::<_, ()>(random_value(env, rng, field))
+        })
+        .unwrap(),
+    }
+}
+
+/// Generates with the answers, returning the problems found.
+fn run_case(template: &Template, values: &HashMap<String, Value>) -> Vec<String> {
+    let mut names = Vec::new();
+    let output = match template.generate_in_with(values, std::env::temp_dir(), |name| {
+        names.push(name.to_string())
+    }) {
+        Ok(output) => output,
+        Err(err) => return vec![format!("render error: {err:#}")],
+    };
+    names.sort();
+    let mut collisions = names
+        .windows(2)
+        .filter(|pair| pair[0] == pair[1])
+        .map(|pair| pair[0].clone())
+        .collect::<Vec<_>>();
+    collisions.dedup();
+    let mut problems = collisions
+        .into_iter()
+        .map(|name| format!("path collision: '{name}'"))
+        .collect::<Vec<_>>();
+    problems.extend(lint_output(&output));
+    problems
+}
+
+impl Fuzz {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let path = self.src.resolve(&config.prefix)?;
+        let template = source::load_gated_template(&path, config, self.allow)?;
+        let seed = self.seed.unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_nanos() as u64)
+                .unwrap_or(1)
+        });
+        println!("Fuzzing with seed {seed} ({} runs)...", self.runs);
+        // xorshift gets stuck at zero
+        let mut rng = Rng(seed.max(1));
+        let builtins = builtin_values(config)?;
+        let mut failures = 0;
+        for run in 1..=self.runs {
+            let mut values = builtins.clone();
+            let mut answers = toml::Table::new();
+            let problems = resolve_variables(&template, &mut values, |name, variable, _| {
+                let value = random_value(&template.environment, &mut rng, variable);
+                // choices with fields are recorded by their values, as in answers files
+                let answer = match value.get_attr("value") {
+                    Ok(inner) if !inner.is_undefined() => inner,
+                    _ => value.clone(),
+                };
+                if let Ok(answer) = toml::Value::try_from(&answer) {
+                    answers.insert(name.to_string(), answer);
+                }
+                Ok(value)
+            })
+            .map(|_| run_case(&template, &values))
+            .unwrap_or_else(|err| vec![format!("{err:#}")]);
+            if problems.is_empty() {
+                continue;
+            }
+            failures += 1;
+            println!();
+            println!("run {run} ... FAILED");
+            for problem in &problems {
+                println!("│ {problem}");
+            }
+            println!("│ answers:");
+            for line in toml::to_string(&answers)?.lines() {
+                println!("│     {line}");
+            }
+        }
+        println!();
+        println!("{} passed; {failures} failed", self.runs - failures);
+        if failures > 0 {
+            bail!(
+                "{failures} of {} fuzzed generations failed (seed {seed})",
+                self.runs
+            )
+        }
+        Ok(())
+    }
+}
@@ -1,109 +1,442 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fs::{self, Permissions};
 use std::path::{Path, PathBuf};
 use std::process::{Command, ExitStatus};
 use std::str::FromStr;
 
-use anyhow::{bail, Context as _, Error, Result};
+use anyhow::{bail, Context as _, Result};
 use chrono::prelude::*;
 use clap::Args;
 use minijinja::{Environment, Value};
-use tapgen::metadata::Metadata;
-use tempfile::NamedTempFile;
+use serde_json::json;
+use tapgen::metadata::{self, Metadata, Policy};
+use tempfile::{NamedTempFile, TempDir};
 use walkdir::WalkDir;
 
-use tapgen::template::{Output, Template};
-use tapgen::variable::{Variable, VariableValue};
+use tapgen::coerce::{self, Origin};
+use tapgen::template::{self, Output, Template};
+use tapgen::variable::{self, Choice, Variable, VariableValue};
 
-use crate::config::Config;
-use crate::copy::copy_dir_all;
-use crate::git::{self, Source as GitSource};
-use crate::prefix::Source as PrefixSource;
-use crate::prompt;
+use crate::answers::Answers;
+use crate::config::{Config, HooksPolicy};
+use crate::copy::{self, copy_dir_all, Backup, BackupMode};
+use crate::journal::Journal;
+use crate::source::{self, Allow, Source};
+use crate::{diff, porcelain, prompt, secret, shell, workspace};
+use crate::{get, git};
 
-#[derive(Clone)]
-enum Source {
-    Path(PathBuf),
-    Git(GitSource),
-    Prefix(PrefixSource),
+#[derive(Clone, Args)]
+pub(crate) struct Generate {
+    #[arg(
+        help = "Source of template to be generated from.",
+        value_parser = Source::from_str,
+    )]
+    src: Source,
+    #[arg(
+        long = "with",
+        value_name = "SRC",
+        help = "Source of another template to be composed into the output (repeatable).",
+        value_parser = Source::from_str,
+    )]
+    with: Vec<Source>,
+    #[arg(help = "Destination of generated output to be applied to.")]
+    dst: Option<PathBuf>,
+    #[arg(short = 'O', long = "overwrite", help = "Overwrite existing files.")]
+    overwrite: bool,
+    #[arg(
+        long = "backup",
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "suffix",
+        help = "Back up existing files before overwriting them, as `<name>.bak` or under `.tapgen-backup/` (dir)."
+    )]
+    backup: Option<BackupMode>,
+    #[arg(
+        long = "merge",
+        conflicts_with = "overwrite",
+        help = "Mark differences with existing files as conflicts instead of overwriting them."
+    )]
+    merge: bool,
+    #[arg(
+        short = 'L',
+        long = "local",
+        help = "Generate from a local template under development."
+    )]
+    local: bool,
+    #[arg(
+        long = "tempdir",
+        value_name = "PATH",
+        help = "Directory to create the temporary output in."
+    )]
+    tempdir: Option<PathBuf>,
+    #[arg(
+        short = 'y',
+        long = "yes",
+        help = "Answer confirmations with their defaults, accepting output exceeding the limits."
+    )]
+    yes: bool,
+    #[arg(
+        long = "answers",
+        value_name = "PATH",
+        help = "Answers file to be used instead of prompting (repeatable)."
+    )]
+    answers: Vec<PathBuf>,
+    #[arg(
+        long = "answers-dir",
+        value_name = "DIR",
+        help = "Directory of answers files, each generated into its own destination."
+    )]
+    answers_dir: Option<PathBuf>,
+    #[arg(
+        long = "protect",
+        value_name = "GLOB",
+        help = "Destination paths that must never be overwritten (repeatable)."
+    )]
+    protect: Vec<String>,
+    #[arg(
+        long = "hooks",
+        value_name = "POLICY",
+        help = "Whether to run hooks of the template (overrides config)."
+    )]
+    hooks: Option<HooksPolicy>,
+    #[command(flatten)]
+    allow: Allow,
+    #[arg(
+        short = 'd',
+        long = "define",
+        value_name = "NAME=VALUE",
+        value_parser = parse_define,
+        help = "Value of variable to be used instead of prompting (repeatable)."
+    )]
+    defines: Vec<(String, String)>,
+    #[arg(
+        long = "no-input",
+        help = "Use default values of variables and apply output without prompting."
+    )]
+    no_input: bool,
+    #[arg(
+        long = "save-answers",
+        value_name = "PATH",
+        help = "Save the resolved values to an answers file for replaying later."
+    )]
+    save_answers: Option<PathBuf>,
+    #[arg(
+        long = "dry-run",
+        help = "Show the output and its changes to the destination without applying it."
+    )]
+    dry_run: bool,
+    #[arg(
+        long = "diff",
+        help = "Show the changes to the destination before applying output."
+    )]
+    diff: bool,
+    #[arg(
+        long = "interactive-apply",
+        help = "Select which of the generated files to be applied."
+    )]
+    interactive_apply: bool,
+    #[arg(
+        long = "porcelain",
+        help = "Print progress as newline-delimited json events instead."
+    )]
+    porcelain: bool,
+    #[arg(
+        long = "into-workspace",
+        help = "Register the applied package in the workspace manifest of a parent directory."
+    )]
+    into_workspace: bool,
+    #[arg(skip)]
+    required_by: Vec<PathBuf>, // roots of the templates requiring this one, to detect cycles
 }
 
-impl FromStr for Source {
-    type Err = Error;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if let Ok(source) = GitSource::from_str(s) {
-            return Ok(Self::Git(source));
-        } else if let Ok(source) = PrefixSource::from_str(s) {
-            return Ok(Self::Prefix(source));
+fn parse_define(s: &str) -> Result<(String, String)> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
         }
-        Ok(Self::Path(PathBuf::from(s)))
+        _ => bail!("invalid definition: '{s}' (expected NAME=VALUE)"),
     }
 }
 
-impl Source {
-    fn kind(&self) -> &'static str {
-        match self {
-            Self::Path(_) => "path",
-            Self::Git(source) => {
-                if source.path.is_some() {
-                    "git+path"
-                } else {
-                    "git"
-                }
+impl Generate {
+    /// Generates from `src` with the values of the answers file at `answers` without prompting.
+    pub(crate) fn replay(
+        src: Source,
+        dst: Option<PathBuf>,
+        answers: PathBuf,
+        overwrite: bool,
+    ) -> Self {
+        Self {
+            src,
+            dst,
+            overwrite,
+            merge: false,
+            backup: None,
+            local: false,
+            tempdir: None,
+            yes: false,
+            answers: vec![answers],
+            with: Vec::new(),
+            answers_dir: None,
+            protect: Vec::new(),
+            hooks: None,
+            allow: Allow::default(),
+            defines: Vec::new(),
+            no_input: true,
+            save_answers: None,
+            dry_run: false,
+            diff: false,
+            interactive_apply: false,
+            porcelain: false,
+            into_workspace: false,
+            required_by: Vec::new(),
+        }
+    }
+
+    pub(crate) fn is_porcelain(&self) -> bool {
+        self.porcelain
+    }
+
+    fn should_run_hook(&self, config: &Config, name: &str) -> bool {
+        match self.hooks.unwrap_or(config.hooks) {
+            HooksPolicy::Ask => prompt::confirm(format!("Run {name} hook?"), Some(true)),
+            HooksPolicy::Always => true,
+            HooksPolicy::Never => {
+                say!("Skipped {name} hook (hooks policy: never).");
+                log::info!("skipped {name} hook by policy");
+                false
             }
-            Self::Prefix(_) => "prefix",
         }
     }
 
-    fn resolve(&self, prefix: impl AsRef<Path>) -> Result<PathBuf> {
-        let mut path = match self {
-            Self::Git(source) => source
-                .resolve(prefix)
-                .context(format!("failed to resolve git source: '{source}'"))?,
-            Self::Prefix(source) => prefix.as_ref().join(source),
-            Self::Path(path) => path.clone(),
+    fn is_local(&self) -> bool {
+        self.local || matches!(&self.src, Source::Path(path) if path == Path::new("."))
+    }
+
+    fn resolve_dst(&self, config: &Config, template: &Template) -> Result<PathBuf> {
+        if let Some(dst) = &self.dst {
+            return Ok(dst.clone());
+        }
+        if self.is_local() {
+            let tempdir = TempDir::with_prefix("tapgen-preview-")
+                .context("failed to create preview directory")?;
+            return Ok(tempdir.into_path());
+        }
+        if let Some(default_dst) = &config.default_dst {
+            let mut values = builtin_values(config)?;
+            values.insert(
+                String::from("_template"),
+                template_value(template, &self.src)?,
+            );
+            let rendered = template
+                .environment
+                .render_str(default_dst, values)
+                .context(format!(
+                    "failed to render default destination: '{default_dst}'"
+                ))?;
+            let dst = expand_tilde(rendered.trim());
+            log::info!("using default destination '{}'", dst.display());
+            return Ok(dst);
+        }
+        std::env::current_dir().context("failed to locate current directory")
+    }
+
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        prompt::set_assume_yes(self.yes || self.no_input);
+        porcelain::set_enabled(self.porcelain);
+        porcelain::emit(
+            "resolve",
+            json!({ "source": self.src.to_string(), "kind": self.src.kind() }),
+        );
+        // composed templates are fetched concurrently, as each may need a clone or a download,
+        // then resolved one at a time on this thread, as resolving may prompt
+        if !self.with.is_empty() {
+            let sources = std::iter::once(&self.src)
+                .filter(|_| !self.is_local())
+                .chain(&self.with)
+                .filter(|src| matches!(src, Source::Git(_) | Source::Archive(_)))
+                .cloned()
+                .collect::<Vec<_>>();
+            let results = get::fetch_all(
+                &sources,
+                get::DEFAULT_JOBS,
+                |src| src.prefetch(&config.prefix),
+                |fetched| (if *fetched { "fetched" } else { "cached" }).to_string(),
+            );
+            for result in results {
+                result?;
+            }
+        }
+        let path = if self.is_local() {
+            let Source::Path(path) = &self.src else {
+                bail!(
+                    "local mode requires a path source (source kind: {})",
+                    self.src.kind()
+                )
+            };
+            let mut path = std::env::current_dir()
+                .context("failed to locate current directory")?
+                .join(path);
+            if path.is_dir() {
+                path = template::definition_path(&path);
+            }
+            path
+        } else {
+            self.src.resolve(&config.prefix)?
         };
-        if path.is_dir() {
-            path.push("tapgen.toml");
+        log::info!(
+            "resolved {} source to '{}'",
+            self.src.kind(),
+            path.display()
+        );
+        porcelain::emit("resolved", json!({ "path": path }));
+        let mut templates = vec![source::load_gated_template(&path, config, self.allow)?];
+        for src in &self.with {
+            let path = src.resolve(&config.prefix)?;
+            log::info!(
+                "resolved composed {} source to '{}'",
+                src.kind(),
+                path.display()
+            );
+            porcelain::emit("resolved", json!({ "path": path }));
+            templates.push(source::load_gated_template(&path, config, self.allow)?);
+        }
+        let template = &templates[0];
+        if self.required_by.contains(&template.root) {
+            bail!(
+                "required templates form a cycle: '{}'",
+                template.root.display()
+            )
+        }
+        let dst = self.resolve_dst(config, template)?;
+        for template in &templates {
+            print_template_metadata(&template.metadata);
+        }
+        // the preview directory of local mode needs no validation
+        let is_preview = self.is_local() && self.dst.is_none();
+        if !is_preview && !validate_dst(config, template, &dst)? {
+            bail!("aborted due to validation warnings")
+        }
+        for (name, _) in &self.defines {
+            if !templates
+                .iter()
+                .any(|template| template.variables.contains_key(name))
+            {
+                bail!("undeclared variable defined on command line: '{name}'")
+            }
+        }
+        if !is_preview {
+            for template in &templates {
+                self.require_templates(config, template, &dst)?;
+            }
+        }
+
+        let mut answers = self
+            .answers
+            .iter()
+            .map(Answers::load)
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(dir) = &self.answers_dir {
+            answers.extend(Answers::load_dir(dir)?);
+        }
+        if self.save_answers.is_some() && (answers.len() > 1 || self.answers_dir.is_some()) {
+            bail!("cannot save answers of more than one generation")
+        }
+        match answers.as_slice() {
+            [] => self.generate(config, &templates, &dst, None),
+            [answers] if self.answers_dir.is_none() => {
+                self.generate(config, &templates, &dst, Some(answers))
+            }
+            answers => {
+                for answers in answers {
+                    say!();
+                    say!("Generating with answers '{}'...", answers.name);
+                    self.generate(config, &templates, &dst.join(&answers.name), Some(answers))
+                        .context(format!(
+                            "failed to generate with answers '{}'",
+                            answers.name
+                        ))?;
+                }
+                Ok(())
+            }
         }
-        path.canonicalize().context(format!(
-            "failed to resolve path: '{}' (source kind: {})",
-            path.display(),
-            self.kind()
-        ))
     }
-}
 
-#[derive(Clone, Args)]
-pub(crate) struct Generate {
-    #[arg(
-        help = "Source of template to be generated from.",
-        value_parser = Source::from_str,
-    )]
-    src: Source,
-    #[arg(
-        help = "Destination of generated output to be applied to.",
-        default_value = std::env::current_dir()
-            .expect("failed to locate current directory")
-            .into_os_string(),
-        )]
-    dst: PathBuf,
-    #[arg(short = 'O', long = "overwrite", help = "Overwrite existing files.")]
-    overwrite: bool,
-}
+    /// Ensures the templates required by `template` have been applied to `dst`,
+    /// offering to generate those missing into it first.
+    fn require_templates(&self, config: &Config, template: &Template, dst: &Path) -> Result<()> {
+        for required in &template.metadata.requires_templates {
+            if dst.join(&required.marker).exists() {
+                continue;
+            }
+            say!();
+            say!(
+                "Template '{}' requires '{}' to be applied first ('{}' is missing).",
+                template.metadata.name,
+                required.source,
+                required.marker.display()
+            );
+            if self.no_input
+                || self.dry_run
+                || !prompt::confirm(format!("Generate '{}' first?", required.source), Some(true))
+            {
+                bail!(
+                    "required template '{}' has not been applied to '{}'",
+                    required.source,
+                    dst.display()
+                )
+            }
+            // relative paths are relative to the requiring template, as with `__extends__`
+            let local = template.root.join(&required.source);
+            let src = if local.exists() {
+                Source::Path(local)
+            } else {
+                Source::from_str(&required.source)?
+            };
+            log::info!("generating required template '{}'", required.source);
+            let mut required_by = self.required_by.clone();
+            required_by.push(template.root.clone());
+            Self {
+                src,
+                with: Vec::new(),
+                // the required template is generated into the destination, not previewed
+                local: false,
+                dst: Some(dst.to_path_buf()),
+                answers: Vec::new(),
+                answers_dir: None,
+                defines: Vec::new(),
+                save_answers: None,
+                required_by,
+                ..self.clone()
+            }
+            .run(config)?;
+            if !dst.join(&required.marker).exists() {
+                bail!(
+                    "required template '{}' did not generate '{}'",
+                    required.source,
+                    required.marker.display()
+                )
+            }
+        }
+        Ok(())
+    }
 
-impl Generate {
-    pub(crate) fn run(&self, config: &Config) -> Result<()> {
-        let path = self.src.resolve(&config.prefix)?;
-        let template = Template::load(&path)
-            .context(format!("failed to load template from '{}'", path.display()))?;
-        print_template_metadata(&template.metadata);
-        {
+    /// Generates from the templates composed in order, the first being the primary one.
+    fn generate(
+        &self,
+        config: &Config,
+        templates: &[Template],
+        dst: &Path,
+        answers: Option<&Answers>,
+    ) -> Result<()> {
+        let template = &templates[0];
+        for template in templates {
             let script = template.root.join("tapgen.before.hook");
-            if script.exists() {
-                println!();
-                if prompt::confirm("Run before hook?", Some(true)) {
+            if script.exists() && !self.dry_run {
+                say!();
+                if self.should_run_hook(config, "before") {
                     let status = run_hook_script(&script, &template.root)?;
                     if !status.success() {
                         bail!("before hook failed with {status}")
@@ -111,59 +444,140 @@ impl Generate {
                 }
             }
         }
-        println!();
-        let mut values = HashMap::new();
-        {
-            if git::check_installed()? {
-                values.insert(
-                    String::from("_git"),
-                    Value::from_serializable(&git::obtain_config()?),
-                );
+        say!();
+        let mut values = builtin_values(config)?;
+        values.insert(
+            String::from("_template"),
+            template_value(template, &self.src)?,
+        );
+        if let Some(recorded) = answers.and_then(|answers| answers.header_str("__commit__")) {
+            let commit = values["_template"]
+                .get_attr("commit")
+                .ok()
+                .filter(|commit| !commit.is_none())
+                .map(|commit| commit.to_string());
+            if commit.as_deref() != Some(recorded) {
+                say!("Warning: answers were saved with the template at commit {recorded}.");
+                log::warn!("template commit {commit:?} differs from recorded commit {recorded}");
             }
         }
-        {
-            let now = Local::now();
-            values.insert(
-                String::from("_now"),
-                Value::from_serializable(&HashMap::from([
-                    ("year", now.year() as u32),
-                    ("month", now.month()),
-                    ("day", now.day()),
-                    ("hour", now.hour()),
-                    ("minute", now.minute()),
-                    ("second", now.second()),
-                ])),
-            );
-        }
-        {
-            for (name, variable) in &template.variables {
-                if let Some(condition) = &variable.condition {
-                    if !condition
-                        .eval(&values)
-                        .context(format!(
-                            "failed to evaluate condition for variable: '{name}'"
-                        ))?
-                        .is_true()
-                    {
-                        continue;
+        let mut group = None;
+        let mut answered = HashSet::new();
+        for template in templates {
+            resolve_variables(template, &mut values, |name, variable, values| {
+                // variables shared by composed templates are answered once
+                if !answered.insert(name.to_string()) {
+                    return Ok(values[name].clone());
+                }
+                if variable.is_secret() {
+                    if let Some(secret) = secret::resolve(config, name, variable)? {
+                        return Ok(Value::from(secret));
                     }
                 }
-                let value = prompt_variable(variable);
-                values.insert(name.clone(), value);
+                if let Some((_, define)) = self.defines.iter().rev().find(|(n, _)| n == name) {
+                    return Ok(coerce::from_str(
+                        &template.environment,
+                        name,
+                        variable,
+                        define,
+                        Origin::CommandLine,
+                    )?);
+                }
+                if let Ok(value) = std::env::var(format!("TAPGEN_VAR_{}", name.to_uppercase())) {
+                    return Ok(coerce::from_str(
+                        &template.environment,
+                        name,
+                        variable,
+                        &value,
+                        Origin::Environment,
+                    )?);
+                }
+                match answers.and_then(|answers| answers.values.get(name)) {
+                    Some(answer) => Ok(coerce::from_toml(
+                        &template.environment,
+                        name,
+                        variable,
+                        answer,
+                        Origin::AnswersFile,
+                    )?),
+                    None if self.no_input => default_variable(template, name, variable, values),
+                    None => Ok({
+                        if variable.group.is_some() && variable.group != group {
+                            group = variable.group.clone();
+                            print_group_header(template, group.as_deref().unwrap());
+                        }
+                        porcelain::emit(
+                            "prompt",
+                            json!({ "variable": name, "prompt": variable.prompt }),
+                        );
+                        // a broken default should not throw away the answers so far
+                        let default = match render_default(template, variable, values) {
+                            Ok(default) => default,
+                            Err(err) => {
+                                log::warn!(
+                                    "failed to evaluate default of variable '{name}': {err:#}"
+                                );
+                                say!(
+                                    "Warning: failed to evaluate default ({err:#}), prompting without it."
+                                );
+                                Some(String::new())
+                            }
+                        };
+                        prompt_variable(
+                            &template.environment,
+                            variable,
+                            default.as_deref(),
+                            |value| render_preview(&template.environment, variable, values, value),
+                        )
+                    }),
+                }
+            })?;
+        }
+        if let Some(path) = &self.save_answers {
+            let commit = values
+                .get("_template")
+                .and_then(|template| template.get_attr("commit").ok())
+                .filter(|commit| !commit.is_none())
+                .map(|commit| commit.to_string());
+            // local paths are recorded absolutely so that they can be resolved from anywhere
+            let source = match &self.src {
+                Source::Path(_) => template.root.display().to_string(),
+                source => source.to_string(),
+            };
+            let mut table = toml::Table::new();
+            for template in templates {
+                table.extend(answers_table(template, &values)?);
             }
+            Answers::save(
+                path,
+                &source,
+                commit.as_deref(),
+                &template.metadata.name,
+                table,
+            )?;
         }
-        println!();
-        println!("Generating from template...");
-        let output = template
-            .generate(&values)
-            .context("failed to generate from template")?;
-        println!("Successfully generated output to temporary directory!");
-        println!("=> '{}'", output.path().display());
-        {
+        say!();
+        say!("Generating from template...");
+        let outputs = templates
+            .iter()
+            .map(|template| {
+                match self.tempdir.as_ref().or(config.tempdir.as_ref()) {
+                    Some(dir) => template.generate_in_with(&values, dir, emit_render),
+                    None => template.generate_in_with(&values, std::env::temp_dir(), emit_render),
+                }
+                .context(format!(
+                    "failed to generate from template: '{}'",
+                    template.metadata.name
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        say!("Successfully generated output to temporary directory!");
+        say!("=> '{}'", outputs[0].path().display());
+        for (template, output) in templates.iter().zip(&outputs) {
             let script = template.root.join("tapgen.after.hook");
-            if script.exists() {
-                println!();
-                if prompt::confirm("Run after hook?", Some(true)) {
+            if script.exists() && !self.dry_run {
+                say!();
+                if self.should_run_hook(config, "after") {
                     let status = run_hook_script(
                         render_hook_script_as_template(script, &template.environment, &values)?,
                         output.base(),
@@ -174,34 +588,396 @@ impl Generate {
                 }
             }
         }
+        let output = compose_outputs(templates, outputs)?;
+        {
+            let (files, bytes) = measure_output(&output);
+            if files > config.max_files || bytes > config.max_bytes {
+                say!();
+                say!(
+                    "Output contains {files} files ({bytes} bytes), exceeding the limits of {} files ({} bytes).",
+                    config.max_files, config.max_bytes
+                );
+                if !prompt::assume_yes() && !prompt::confirm("Continue anyway?", Some(false)) {
+                    output
+                        .into_tempdir()
+                        .close()
+                        .context("failed to dispose output")?;
+                    say!("Disposed output!");
+                    porcelain::emit("disposed", json!({}));
+                    return Ok(());
+                }
+            }
+        }
         {
-            println!();
+            let warnings = lint_output(&output);
+            if !warnings.is_empty() {
+                say!();
+                say!("[Warnings]");
+                for warning in &warnings {
+                    log::warn!("{warning}");
+                    say!("│ {warning}");
+                    porcelain::emit("warning", json!({ "message": warning }));
+                }
+            }
+        }
+        if self.dry_run {
+            say!();
             inspect_output(&output);
-            confirm_output(output, &self.dst, self.overwrite)?;
+            say!();
+            say!("[Changes]");
+            diff::print_changes(output.path(), dst);
+            output
+                .into_tempdir()
+                .close()
+                .context("failed to dispose output")?;
+            say!("Disposed output! (dry run)");
+            porcelain::emit("disposed", json!({ "dry_run": true }));
+            return Ok(());
+        }
+        {
+            say!();
+            inspect_output(&output);
+            if self.diff {
+                say!();
+                say!("[Changes]");
+                diff::print_changes(output.path(), dst);
+                say!();
+            }
+            if self.interactive_apply {
+                select_output(&output)?;
+            }
+            let options = copy::Options {
+                force: self.overwrite,
+                merge_tool: config.merge_tool.clone(),
+                merge: self.merge,
+                backup: self.backup.map(|mode| match mode {
+                    BackupMode::Suffix => Backup::Suffix,
+                    BackupMode::Dir => Backup::Dir(
+                        dst.join(copy::BACKUP_DIR)
+                            .join(Local::now().format("%Y%m%d%H%M%S").to_string()),
+                    ),
+                }),
+                journal: RefCell::new(Journal::new(&config.prefix, dst)),
+                protect: self
+                    .protect
+                    .iter()
+                    .chain(&config.apply.protect)
+                    .map(|pattern| {
+                        glob::Pattern::new(pattern)
+                            .context(format!("invalid protect pattern: '{pattern}'"))
+                    })
+                    .collect::<Result<_>>()?,
+                // conflicts are only overwritten with --overwrite, never by assuming yes
+                interactive: !self.no_input && !self.yes,
+                modes: template.metadata.permissions.clone(),
+                file_mode: parse_config_mode(config.apply.file_mode.as_deref())?,
+                dir_mode: parse_config_mode(config.apply.dir_mode.as_deref())?,
+            };
+            if let Some(target) = confirm_output(output, dst, &options)? {
+                if self.into_workspace {
+                    say!();
+                    workspace::register(&target)?;
+                }
+                for template in templates {
+                    run_verify_commands(&template.metadata, &target)?;
+                }
+            }
         }
         Ok(())
     }
 }
 
+/// Merges the outputs of composed templates into the first, in order, resolving a path emitted
+/// by more than one template by the `__conflicts__` policy of the later template.
+fn compose_outputs(templates: &[Template], outputs: Vec<Output>) -> Result<Output> {
+    let mut outputs = outputs.into_iter();
+    let first = outputs.next().expect("at least one template");
+    let base = first.base();
+    for (template, output) in templates[1..].iter().zip(outputs) {
+        for entry in WalkDir::new(output.base()).min_depth(1) {
+            let entry = entry?;
+            let name = entry.path().strip_prefix(output.base())?;
+            let target = base.join(name);
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)?;
+                continue;
+            }
+            // symlinks are neither followed nor required to point to existing paths
+            let existing = target.symlink_metadata().ok();
+            if existing.is_some() {
+                match template.metadata.conflicts.policy(name) {
+                    Policy::FirstWins => {
+                        log::info!("kept '{}' of an earlier template", name.display());
+                        continue;
+                    }
+                    Policy::LastWins => log::info!(
+                        "overwrote '{}' with template '{}'",
+                        name.display(),
+                        template.metadata.name
+                    ),
+                    Policy::Error => bail!(
+                        "'{}' is generated by more than one template (conflicts: {})",
+                        name.display(),
+                        Policy::Error
+                    ),
+                }
+            }
+            if let Some(metadata) = existing {
+                // a link is replaced, not written through
+                if metadata.is_symlink() || entry.path_is_symlink() {
+                    fs::remove_file(&target)?;
+                }
+            }
+            if entry.path_is_symlink() {
+                let link = fs::read_link(entry.path())?;
+                template::symlink(name, &link, &target, entry.path().is_dir())
+                    .context(format!("failed to compose '{}'", name.display()))?;
+            } else {
+                fs::copy(entry.path(), &target)
+                    .context(format!("failed to compose '{}'", name.display()))?;
+            }
+        }
+        output
+            .into_tempdir()
+            .close()
+            .context("failed to dispose output")?;
+    }
+    Ok(first)
+}
+
+fn parse_config_mode(mode: Option<&str>) -> Result<Option<u32>> {
+    mode.map(|mode| metadata::parse_mode(mode).map_err(anyhow::Error::msg))
+        .transpose()
+}
+
+/// Expands a leading `~` of `path` to the home directory.
+fn expand_tilde(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            let home = home::home_dir().expect("failed to locate user home directory");
+            home.join(rest.trim_start_matches(['/', '\\']))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Canonicalizes `path` even if it does not exist yet, by its nearest existing ancestor.
+pub(crate) fn canonicalize_lenient(path: &Path) -> PathBuf {
+    let mut rest = Vec::new();
+    let mut ancestor = path;
+    loop {
+        if let Ok(canonical) = ancestor.canonicalize() {
+            return canonical.join(rest.into_iter().rev().collect::<PathBuf>());
+        }
+        match (ancestor.parent(), ancestor.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                ancestor = parent;
+            }
+            _ => return path.to_path_buf(),
+        }
+    }
+}
+
+/// Warns about destinations that are likely mistakes, returning whether to proceed.
+fn validate_dst(config: &Config, template: &Template, dst: &Path) -> Result<bool> {
+    let dst = canonicalize_lenient(dst);
+    let mut warnings = Vec::new();
+    if dst.starts_with(&template.root) {
+        warnings.push(String::from("Destination is inside the template."));
+    }
+    if dst.starts_with(canonicalize_lenient(&config.prefix)) {
+        warnings.push(String::from("Destination is inside the prefix."));
+    }
+    if dst.is_dir() && git::check_installed()? {
+        let is_empty = fs::read_dir(&dst)
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(true);
+        let repository = git::Repository::new(&dst);
+        if !is_empty && repository.is_worktree()? && repository.is_dirty()? {
+            warnings.push(String::from(
+                "Destination is a git repository with uncommitted changes.",
+            ));
+        }
+    }
+    if warnings.is_empty() {
+        return Ok(true);
+    }
+    say!();
+    for warning in &warnings {
+        log::warn!("{warning} ('{}')", dst.display());
+        say!("Warning: {warning}");
+    }
+    Ok(prompt::confirm("Continue anyway?", Some(false)))
+}
+
+pub(crate) fn resolve_variables(
+    template: &Template,
+    values: &mut HashMap<String, Value>,
+    mut resolve: impl FnMut(&str, &Variable, &HashMap<String, Value>) -> Result<Value>,
+) -> Result<()> {
+    // groups are decided upon their first variable, so later answers cannot reopen them
+    let mut groups = HashMap::new();
+    for (name, variable) in &template.variables {
+        if let Some(group) = &variable.group {
+            let enabled = match groups.get(group) {
+                Some(enabled) => *enabled,
+                None => {
+                    let enabled = match &template.metadata.groups[group].condition {
+                        Some(condition) => condition
+                            .eval(&template.environment, &*values)
+                            .context(format!("failed to evaluate condition for group: '{group}'"))?
+                            .is_true(),
+                        None => true,
+                    };
+                    groups.insert(group.clone(), enabled);
+                    enabled
+                }
+            };
+            if !enabled {
+                continue;
+            }
+        }
+        if let Some(condition) = &variable.condition {
+            if !condition
+                .eval(&template.environment, &*values)
+                .context(format!(
+                    "failed to evaluate condition for variable: '{name}'"
+                ))?
+                .is_true()
+            {
+                continue;
+            }
+        }
+        let value = resolve(name, variable, values)?;
+        log::debug!("answered variable '{name}'");
+        values.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+pub(crate) fn answers_table(
+    template: &Template,
+    values: &HashMap<String, Value>,
+) -> Result<toml::Table> {
+    let mut table = toml::Table::new();
+    for (name, variable) in &template.variables {
+        if variable.is_secret() {
+            continue;
+        }
+        let Some(value) = values.get(name) else {
+            continue;
+        };
+        // choices with fields are recorded by their values
+        let value = match value.get_attr("value") {
+            Ok(inner) if !inner.is_undefined() => inner,
+            _ => value.clone(),
+        };
+        let value = toml::Value::try_from(&value)
+            .context(format!("failed to record value of variable: '{name}'"))?;
+        table.insert(name.clone(), value);
+    }
+    Ok(table)
+}
+
+pub(crate) fn template_value(template: &Template, source: &Source) -> Result<Value> {
+    let commit = if git::check_installed()? {
+        git::Repository::new(&template.root).head().ok()
+    } else {
+        None
+    };
+    Ok(Value::from_serializable(&HashMap::from([
+        ("name", Some(template.metadata.name.clone())),
+        ("version", template.metadata.version.clone()),
+        ("source", Some(source.to_string())),
+        ("commit", commit),
+        (
+            "tapgen_version",
+            Some(env!("CARGO_PKG_VERSION").to_string()),
+        ),
+    ])))
+}
+
+pub(crate) fn builtin_values(config: &Config) -> Result<HashMap<String, Value>> {
+    let mut values = HashMap::new();
+    {
+        if git::check_installed()? {
+            values.insert(
+                String::from("_git"),
+                Value::from_serializable(&git::obtain_config()?),
+            );
+        }
+    }
+    {
+        let now = Local::now();
+        values.insert(
+            String::from("_now"),
+            Value::from_serializable(&HashMap::from([
+                ("year", now.year() as u32),
+                ("month", now.month()),
+                ("day", now.day()),
+                ("hour", now.hour()),
+                ("minute", now.minute()),
+                ("second", now.second()),
+            ])),
+        );
+    }
+    {
+        values.insert(
+            String::from("_os"),
+            Value::from_serializable(&HashMap::from([
+                ("name", std::env::consts::OS),
+                ("family", std::env::consts::FAMILY),
+                ("arch", std::env::consts::ARCH),
+            ])),
+        );
+    }
+    {
+        values.insert(
+            String::from("_defaults"),
+            Value::from_serializable(&config.defaults),
+        );
+    }
+    Ok(values)
+}
+
 fn print_template_metadata(metadata: &Metadata) {
-    println!(
+    say!(
         "You are currently using '{}' by {}.",
-        metadata.name, metadata.author
+        metadata.name,
+        metadata.author
     );
     if let Some(description) = &metadata.description {
-        println!("{description}");
+        say!("{description}");
     }
     if let Some(url) = &metadata.url {
-        println!("> {url}");
+        say!("> {url}");
+    }
+}
+
+fn print_group_header(template: &Template, name: &str) {
+    let group = &template.metadata.groups[name];
+    porcelain::emit("group", json!({ "group": name, "title": group.title }));
+    say!();
+    say!("[{}]", group.title);
+    if let Some(description) = &group.description {
+        say!("{description}");
     }
 }
 
 fn run_hook_script(path: impl AsRef<Path>, cwd: impl AsRef<Path>) -> Result<ExitStatus> {
     let path = path.as_ref();
-    Command::new(path)
+    log::info!(
+        "running hook script '{}' in '{}'",
+        path.display(),
+        cwd.as_ref().display()
+    );
+    let status = Command::new(path)
         .current_dir(&cwd)
         .status()
-        .context(format!("failed to run hook script: '{}'", path.display()))
+        .context(format!("failed to run hook script: '{}'", path.display()))?;
+    log::info!("hook script '{}' exited with {status}", path.display());
+    Ok(status)
 }
 
 fn render_hook_script_as_template(
@@ -232,38 +1008,268 @@ fn render_hook_script_as_template(
     Ok(file)
 }
 
-fn prompt_variable(variable: &Variable) -> Value {
+/// Renders the templated default of the variable against the answered `values`.
+pub(crate) fn render_default(
+    template: &Template,
+    variable: &Variable,
+    values: &HashMap<String, Value>,
+) -> Result<Option<String>> {
+    let Some(default) = variable.render_default(&template.environment, values)? else {
+        return Ok(None);
+    };
+    if let VariableValue::String {
+        pattern, choices, ..
+    } = &variable.value
+    {
+        if default.is_empty() {
+            return Ok(Some(default));
+        }
+        if let Some(choices) = choices {
+            if !choices.iter().any(|choice| choice.value == default) {
+                bail!("default {default:?} is not one of the choices")
+            }
+        } else if let Some(pattern) = pattern {
+            if !pattern.is_match(&default) {
+                bail!(
+                    "default {default:?} does not match pattern: `{}`",
+                    pattern.as_str()
+                )
+            }
+        }
+    }
+    Ok(Some(default))
+}
+
+fn default_variable(
+    template: &Template,
+    name: &str,
+    variable: &Variable,
+    values: &HashMap<String, Value>,
+) -> Result<Value> {
+    let default = render_default(template, variable, values)
+        .context(format!("failed to evaluate default of variable: '{name}'"))?;
+    if let VariableValue::String { default: raw, .. } | VariableValue::Path { default: raw, .. } =
+        &variable.value
+    {
+        if default.as_deref().unwrap_or(raw).is_empty() {
+            bail!("variable '{name}' has no default value (required by --no-input)")
+        }
+    }
+    let value = match default {
+        Some(default) => variable.string_value(&default),
+        None => variable.default_value(&template.environment),
+    };
+    if let Err(message) = variable.check(&template.environment, &value) {
+        bail!("default of variable '{name}' is invalid: {message}")
+    }
+    Ok(value)
+}
+
+/// Renders the `preview` of the variable as if it was answered with `value`.
+fn render_preview(
+    env: &Environment<'static>,
+    variable: &Variable,
+    values: &HashMap<String, Value>,
+    value: &Value,
+) -> Option<String> {
+    let preview = variable.preview.as_ref()?;
+    let mut values = values.clone();
+    values.insert(String::from("value"), value.clone());
+    match env.render_str(preview, values) {
+        Ok(rendered) => Some(rendered),
+        Err(err) => {
+            log::warn!("failed to render preview: {err}");
+            None
+        }
+    }
+}
+
+fn no_preview(_: &Value) -> Option<String> {
+    None
+}
+
+fn emit_render(name: &str) {
+    porcelain::emit("render", json!({ "path": name }));
+}
+
+/// Prompts for the variable, with `default` replacing the default of a string variable.
+fn prompt_variable(
+    env: &Environment<'static>,
+    variable: &Variable,
+    default: Option<&str>,
+    preview: impl Fn(&Value) -> Option<String>,
+) -> Value {
+    if variable.is_secret() {
+        // secrets are neither echoed nor previewed
+        return prompt_checked(env, variable, default, no_preview);
+    }
+    let is_choices = matches!(
+        &variable.value,
+        VariableValue::String {
+            choices: Some(_),
+            ..
+        }
+    );
+    if !is_choices {
+        let value = match default {
+            Some(default) => variable.string_value(default),
+            None => variable.default_value(env),
+        };
+        if let Some(preview) = preview(&value) {
+            say!("Preview: {preview}");
+        }
+    }
+    let value = prompt_checked(env, variable, default, &preview);
+    if let Some(preview) = preview(&value) {
+        say!("=> {preview}");
+    }
+    value
+}
+
+/// Prompts for the variable until the answer passes its `validate` expression.
+fn prompt_checked(
+    env: &Environment<'static>,
+    variable: &Variable,
+    default: Option<&str>,
+    preview: impl Fn(&Value) -> Option<String>,
+) -> Value {
+    loop {
+        let value = prompt_variable_value(env, variable, default, &preview);
+        match variable.check(env, &value) {
+            Ok(()) => return value,
+            Err(message) => say!("Invalid input: {message}"),
+        }
+    }
+}
+
+fn prompt_variable_value(
+    env: &Environment<'static>,
+    variable: &Variable,
+    default: Option<&str>,
+    preview: impl Fn(&Value) -> Option<String>,
+) -> Value {
     match &variable.value {
         VariableValue::String {
-            default,
+            default: raw,
             pattern,
             choices,
+            multiline,
+            ..
         } => {
+            let default = default.unwrap_or(raw);
+            // answers are transformed before validation, e.g. lowercased to match the pattern
+            let check = |input: &str| -> Result<String> {
+                let input = variable.transform(env, input)?;
+                if let Some(pattern) = pattern {
+                    if !pattern.is_match(&input) {
+                        bail!("input does not match pattern: `{}`", pattern.as_str())
+                    }
+                }
+                Ok(input)
+            };
+            if *multiline {
+                loop {
+                    let input = prompt::editor(&variable.prompt, default);
+                    // the trailing newline added by most editors is not part of the answer
+                    match check(input.trim_end_matches(['\r', '\n'])) {
+                        Ok(input) => return Value::from(input),
+                        Err(err) => say!("Invalid input: {err:#}"),
+                    }
+                }
+            }
             let default = if default.is_empty() {
                 None
             } else {
-                Some(default.clone())
+                Some(default.to_string())
             };
             if let Some(choices) = choices {
-                Value::from(prompt::select(&variable.prompt, choices, default))
+                let labels = choices
+                    .iter()
+                    .map(|choice| match preview(&choice.to_value()) {
+                        Some(preview) => format!("{choice} ({preview})"),
+                        None => choice.to_string(),
+                    })
+                    .collect::<Vec<_>>();
+                let default = default
+                    .and_then(|default| choices.iter().position(|c| c.value == default))
+                    .map(|i| labels[i].clone());
+                let selected = prompt::select(&variable.prompt, &labels, default);
+                let i = labels.iter().position(|label| *label == selected).unwrap();
+                choices[i].to_value()
             } else {
-                let validator = pattern.as_ref().map(|pattern| {
-                    |input: &String| {
-                        if !pattern.is_match(input) {
-                            let pattern = pattern.as_str();
-                            bail!("input does not match pattern: `{pattern}`")
-                        }
-                        Ok(())
+                // an empty secret falls back to the default
+                let allow_empty = variable.is_secret() && default.is_some();
+                let validator = Some(|input: &String| {
+                    if !(allow_empty && input.is_empty()) {
+                        check(input)?;
                     }
+                    Ok(())
                 });
-                Value::from(prompt::input(&variable.prompt, default, validator))
+                let input = if variable.is_secret() {
+                    let input = prompt::password(&variable.prompt, allow_empty, validator);
+                    match default {
+                        Some(default) if input.is_empty() => return Value::from(default),
+                        _ => input,
+                    }
+                } else {
+                    prompt::input(&variable.prompt, default, validator)
+                };
+                Value::from(check(&input).unwrap_or(input))
             }
         }
-        VariableValue::Array { default, choices } => Value::from(prompt::multi_select(
-            &variable.prompt,
+        VariableValue::Array {
+            default,
             choices,
-            Some(default),
-        )),
+            allow_custom,
+            min,
+            max,
+        } => {
+            let mut items = choices.clone();
+            items.extend(
+                default
+                    .iter()
+                    .filter(|d| !choices.iter().any(|choice| choice.value == **d))
+                    .map(|d| Choice::from(d.clone())),
+            );
+            let defaults = items
+                .iter()
+                .filter(|item| default.contains(&item.value))
+                .cloned()
+                .collect::<Vec<_>>();
+            loop {
+                let mut selected = prompt::multi_select(&variable.prompt, &items, Some(&defaults))
+                    .into_iter()
+                    .map(|choice| choice.value)
+                    .collect::<Vec<_>>();
+                if *allow_custom {
+                    let custom = prompt::input(
+                        "Custom items (comma separated)",
+                        Some(String::new()),
+                        None::<fn(&String) -> Result<()>>,
+                    );
+                    selected.extend(
+                        custom
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|item| !item.is_empty() && !selected.iter().any(|s| s == item))
+                            .map(String::from)
+                            .collect::<Vec<_>>(),
+                    );
+                }
+                match coerce::check_count(*min, *max, selected.len()) {
+                    Ok(()) => break Value::from(selected),
+                    Err(reason) => say!("Invalid selection: {reason}."),
+                }
+            }
+        }
+        VariableValue::Map { fields } => {
+            say!("[{}]", variable.prompt);
+            // fields are answered like top-level variables
+            variable::map_value(env, fields, |_, field| {
+                Ok::<_, ()>(prompt_checked(env, field, None, no_preview))
+            })
+            .unwrap()
+        }
         VariableValue::Integer { default, range } => Value::from(prompt::input(
             &variable.prompt,
             Some(*default),
@@ -276,41 +1282,279 @@ fn prompt_variable(variable: &Variable) -> Value {
                 Ok(())
             }),
         )),
-        VariableValue::Boolean { default } => {
-            Value::from(prompt::confirm(&variable.prompt, Some(*default)))
+        VariableValue::Boolean {
+            default,
+            true_label,
+            false_label,
+            help,
+        } => {
+            if let Some(help) = help {
+                say!("{help}");
+            }
+            if true_label.is_none() && false_label.is_none() {
+                return Value::from(prompt::boolean(&variable.prompt, *default));
+            }
+            let labels = [
+                true_label.clone().unwrap_or_else(|| String::from("Yes")),
+                false_label.clone().unwrap_or_else(|| String::from("No")),
+            ];
+            let default = labels[if *default { 0 } else { 1 }].clone();
+            let selected = prompt::select(&variable.prompt, &labels, Some(default));
+            Value::from(selected == labels[0])
+        }
+        VariableValue::Path {
+            default,
+            kind,
+            must_exist,
+        } => {
+            let input = prompt::input(
+                &variable.prompt,
+                (!default.is_empty()).then(|| default.clone()),
+                Some(|input: &String| {
+                    coerce::resolve_path(*kind, *must_exist, input).map_err(anyhow::Error::msg)?;
+                    Ok(())
+                }),
+            );
+            let path = coerce::resolve_path(*kind, *must_exist, &input)
+                .expect("input should have been validated");
+            Value::from(path.to_string_lossy())
+        }
+        VariableValue::Date { default, format } => {
+            let input = prompt::input(
+                format!("{} ({format})", variable.prompt),
+                coerce::parse_date(format, default).ok(),
+                Some(|input: &String| {
+                    coerce::parse_date(format, input).map_err(anyhow::Error::msg)?;
+                    Ok(())
+                }),
+            );
+            Value::from(
+                coerce::parse_date(format, &input).expect("input should have been validated"),
+            )
         }
     }
 }
 
 fn inspect_output(output: &Output) {
     // TODO: improve output readability
-    println!("[Output]");
+    say!("[Output]");
     let walker = WalkDir::new(output.base());
     for entry in walker {
         let entry = entry.unwrap();
         let depth = entry.depth();
         let indent = " ".repeat(depth * 4);
-        println!("│ {}{}", indent, entry.file_name().to_string_lossy());
+        say!("│ {}{}", indent, entry.file_name().to_string_lossy());
     }
 }
 
-fn confirm_output(output: Output, dst: impl AsRef<Path>, force: bool) -> Result<()> {
+pub(crate) fn lint_output(output: &Output) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (source, rendered) in output.rendered() {
+        let name = rendered
+            .strip_prefix(output.path())
+            .unwrap_or(rendered)
+            .display();
+        let Ok(contents) = fs::read_to_string(rendered) else {
+            continue;
+        };
+        if contents.trim().is_empty() {
+            let is_source_empty = fs::read_to_string(source)
+                .map(|source| source.trim().is_empty())
+                .unwrap_or(true);
+            if !is_source_empty {
+                warnings.push(format!("'{name}' is empty but its source is not"));
+            }
+        }
+        for (n, line) in contents.lines().enumerate() {
+            if line.contains("{{") || line.contains("{%") {
+                warnings.push(format!(
+                    "'{name}' contains leftover template syntax at line {}",
+                    n + 1
+                ));
+                break;
+            }
+        }
+    }
+    warnings
+}
+
+/// Removes the generated files that are not selected to be applied.
+fn select_output(output: &Output) -> Result<()> {
+    let files = WalkDir::new(output.path())
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            entry
+                .path()
+                .strip_prefix(output.path())
+                .unwrap()
+                .to_string_lossy()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    let selected = prompt::multi_select("Files to be applied", &files, Some(&files));
+    let mut deselected = 0;
+    for file in files.iter().filter(|file| !selected.contains(file)) {
+        let path = output.path().join(file);
+        fs::remove_file(&path).context(format!("failed to remove file: '{}'", path.display()))?;
+        log::debug!("deselect '{file}'");
+        deselected += 1;
+    }
+    say!("Deselected {deselected} files.");
+    Ok(())
+}
+
+fn measure_output(output: &Output) -> (u64, u64) {
+    let (mut files, mut bytes) = (0, 0);
+    for entry in WalkDir::new(output.path())
+        .into_iter()
+        .filter_map(Result::ok)
+    {
+        if entry.file_type().is_file() {
+            files += 1;
+            bytes += entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        }
+    }
+    (files, bytes)
+}
+
+fn rename_output(
+    base: &Path,
+    target: &Path,
+    dst: &Path,
+    journal: &mut Journal,
+) -> Result<Option<u32>> {
+    if target.exists() {
+        return Ok(None);
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).context(format!(
+            "failed to create destination directory: '{}'",
+            parent.display()
+        ))?;
+    }
+    let files = WalkDir::new(base)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .count() as u32;
+    match fs::rename(base, target) {
+        Ok(()) => {
+            log::info!("applied output by renaming to '{}'", target.display());
+            porcelain::emit("apply", json!({ "path": target, "action": "rename" }));
+            for entry in WalkDir::new(target).into_iter().filter_map(Result::ok) {
+                let name = entry.path().strip_prefix(dst).unwrap();
+                if entry.file_type().is_dir() {
+                    journal.created_dir(name);
+                } else {
+                    journal.created(name);
+                }
+            }
+            Ok(Some(files))
+        }
+        Err(err) => {
+            log::debug!("cannot apply output by renaming: {err}");
+            Ok(None)
+        }
+    }
+}
+
+fn confirm_output(
+    output: Output,
+    dst: impl AsRef<Path>,
+    options: &copy::Options,
+) -> Result<Option<PathBuf>> {
+    let base = output.base();
+    let target = dst.as_ref().join(output.basename());
     let tempdir = output.into_tempdir();
     if prompt::confirm(
-        if force {
+        if options.force {
             "Apply output (force overwrite)?"
         } else {
             "Apply output?"
         },
         Some(true),
     ) {
-        let (c, o, s) =
-            copy_dir_all(&dst, tempdir, &dst, force).context("failed to apply output")?;
-        println!("Successfully applied output to destination!");
-        println!("Created {c} files. Overwritten {o} files. Skipped {s} files.");
+        log::info!("applying output to '{}'", dst.as_ref().display());
+        // only the last apply can be undone
+        options.journal.borrow().clear()?;
+        let applied = (|| -> Result<(u32, u32, u32)> {
+            let renamed = rename_output(
+                &base,
+                &target,
+                dst.as_ref(),
+                &mut options.journal.borrow_mut(),
+            )?;
+            match renamed {
+                Some(c) => {
+                    copy::apply_modes(dst.as_ref(), &target, options)?;
+                    Ok((c, 0, 0))
+                }
+                None => {
+                    copy_dir_all(&dst, tempdir, &dst, options).context("failed to apply output")
+                }
+            }
+        })();
+        // a partial apply is journaled as well, so that it can be undone
+        let saved = options.journal.take().save();
+        let (c, o, s) = applied?;
+        saved?;
+        log::info!("applied output: {c} created, {o} overwritten, {s} skipped");
+        say!("Successfully applied output to destination!");
+        say!("=> '{}'", dst.as_ref().display());
+        say!("Created {c} files. Overwritten {o} files. Skipped {s} files.");
+        porcelain::emit(
+            "done",
+            json!({
+                "destination": dst.as_ref(),
+                "created": c,
+                "overwritten": o,
+                "skipped": s,
+            }),
+        );
+        Ok(Some(target))
     } else {
         tempdir.close().context("failed to dispose output")?;
-        println!("Disposed output!");
+        log::info!("disposed output");
+        say!("Disposed output!");
+        porcelain::emit("disposed", json!({}));
+        Ok(None)
+    }
+}
+
+fn run_verify_commands(metadata: &Metadata, cwd: impl AsRef<Path>) -> Result<()> {
+    if metadata.verify.is_empty() {
+        return Ok(());
+    }
+    let cwd = cwd.as_ref();
+    let mut failures = 0;
+    let mut results = Vec::new();
+    say!();
+    for (name, command) in &metadata.verify {
+        say!("Verifying '{name}': {command}");
+        let status = shell::command(command)
+            .current_dir(cwd)
+            .status()
+            .context(format!("failed to run verify command: '{name}'"))?;
+        log::info!("verify command '{name}' exited with {status}");
+        if !status.success() {
+            failures += 1;
+        }
+        results.push((name, status));
+    }
+    say!();
+    say!("[Verify]");
+    for (name, status) in &results {
+        if status.success() {
+            say!("│ {name}: passed");
+        } else {
+            say!("│ {name}: failed ({status})");
+        }
+    }
+    if failures > 0 {
+        bail!("{failures} of {} verify commands failed", results.len())
     }
     Ok(())
 }
@@ -0,0 +1,139 @@
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::cache;
+use crate::config::Config;
+use crate::prefix::Index;
+use crate::source::Source;
+
+pub(crate) const DEFAULT_JOBS: usize = 4;
+
+/// Runs `fetch` over the sources concurrently with at most `jobs` at a time,
+/// printing one line per source as it completes, as told by `describe`.
+pub(crate) fn fetch_all<T: Send>(
+    sources: &[Source],
+    jobs: usize,
+    fetch: impl Fn(&Source) -> Result<T> + Sync,
+    describe: impl Fn(&T) -> String + Sync,
+) -> Vec<Result<T>> {
+    let next = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let results = Mutex::new((0..sources.len()).map(|_| None).collect::<Vec<_>>());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, sources.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let Some(source) = sources.get(i) else {
+                    break;
+                };
+                let result = fetch(source);
+                let n = done.fetch_add(1, Ordering::SeqCst) + 1;
+                match &result {
+                    Ok(fetched) => {
+                        say!("[{n}/{}] {source}: {}", sources.len(), describe(fetched))
+                    }
+                    Err(err) => {
+                        log::warn!("failed to fetch '{source}': {err:#}");
+                        say!("[{n}/{}] {source}: failed ({err:#})", sources.len())
+                    }
+                }
+                results.lock().unwrap()[i] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every source should be fetched"))
+        .collect()
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Get {
+    #[arg(
+        required = true,
+        help = "Sources of templates to be fetched into the prefix.",
+        value_parser = Source::from_str,
+    )]
+    srcs: Vec<Source>,
+    #[arg(
+        short = 'j',
+        long = "jobs",
+        value_name = "N",
+        default_value_t = DEFAULT_JOBS,
+        help = "Number of sources to be fetched at a time."
+    )]
+    jobs: usize,
+}
+
+impl Get {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let index = Index::load(&config.prefix)?;
+        let mut sources = Vec::new();
+        for src in &self.srcs {
+            // indexed names may refer to git sources
+            let src = match src {
+                Source::Prefix(source) => match index.find(&source.name()) {
+                    Some(entry) => Source::from_str(&entry.source).unwrap_or_else(|_| src.clone()),
+                    None => src.clone(),
+                },
+                src => src.clone(),
+            };
+            match src {
+                Source::Git(_) | Source::Archive(_) => sources.push(src),
+                src => println!("{src}: nothing to fetch ({} source)", src.kind()),
+            }
+        }
+        let results = fetch_all(
+            &sources,
+            self.jobs,
+            |src| match src {
+                Source::Git(source) => source.fetch(&config.prefix).map(|f| f.to_string()),
+                // archives are downloaded again, as their urls may point to the latest release
+                Source::Archive(source) => source
+                    .fetch(&config.prefix)
+                    .map(|_| String::from("downloaded")),
+                _ => unreachable!(),
+            },
+            String::clone,
+        );
+        for (source, result) in sources.iter().zip(&results) {
+            if result.is_err() {
+                continue;
+            }
+            // archives are extracted under names that do not tell their sources
+            let (path, origin) = match source {
+                Source::Git(source) => (source.local_path(&config.prefix), None),
+                Source::Archive(source) => {
+                    (source.local_path(&config.prefix)?, Some(source.to_string()))
+                }
+                _ => unreachable!(),
+            };
+            let path = path.join("tapgen.toml");
+            if path.exists() {
+                if let Err(err) =
+                    cache::update_catalog(&config.prefix, path.canonicalize()?, origin)
+                {
+                    log::warn!("cannot update catalog: {err:#}");
+                }
+            }
+        }
+        let failures = results.iter().filter(|result| result.is_err()).count();
+        println!();
+        println!(
+            "Fetched {} of {} sources.",
+            results.len() - failures,
+            results.len()
+        );
+        if failures > 0 {
+            bail!("failed to fetch {failures} sources")
+        }
+        Ok(())
+    }
+}
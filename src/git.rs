@@ -1,19 +1,26 @@
 use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read as _, Write as _};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
 
 use anyhow::{bail, Context as _, Error, Result};
 use regex::Regex;
+use walkdir::WalkDir;
 
 use crate::{git, prompt};
 
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub(crate) enum Host {
     GitHub,
     GitLab,
     BitBucket,
+    Other(String), // domain of ssh remote
 }
 
 impl FromStr for Host {
@@ -29,27 +36,62 @@ impl FromStr for Host {
     }
 }
 
+impl Host {
+    pub(crate) fn name(&self) -> &str {
+        match self {
+            Self::GitHub => "github",
+            Self::GitLab => "gitlab",
+            Self::BitBucket => "bitbucket",
+            Self::Other(domain) => domain,
+        }
+    }
+
+    fn from_domain(domain: &str) -> Self {
+        match domain {
+            "github.com" => Self::GitHub,
+            "gitlab.com" => Self::GitLab,
+            "bitbucket.org" => Self::BitBucket,
+            _ => Self::Other(domain.to_string()),
+        }
+    }
+}
+
 impl std::fmt::Display for Host {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::GitHub => write!(f, "github.com"),
             Self::GitLab => write!(f, "gitlab.com"),
             Self::BitBucket => write!(f, "bitbucket.org"),
+            Self::Other(domain) => write!(f, "{domain}"),
         }
     }
 }
 
+/// Whether any repository is cached under the directory.
+fn holds_repositories(dir: &Path) -> bool {
+    WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(Result::ok)
+        .any(|entry| entry.file_name() == ".git")
+}
+
 #[derive(Clone)]
 pub(crate) struct Source {
     host: Host,
     owner: String,
     repo: String,
+    reference: Option<String>,
     pub(crate) path: Option<PathBuf>,
+    ssh: Option<String>, // remote url, if cloned over ssh
 }
 
 impl std::fmt::Display for Source {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "https://{}/{}/{}.git", self.host, self.owner, self.repo)
+        match &self.ssh {
+            Some(url) => write!(f, "{url}"),
+            None => write!(f, "https://{}/{}/{}.git", self.host, self.owner, self.repo),
+        }
     }
 }
 
@@ -58,17 +100,65 @@ impl FromStr for Source {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         static PATTERN: OnceLock<Regex> = OnceLock::new();
+        static NESTED_PATTERN: OnceLock<Regex> = OnceLock::new();
+        static SSH_PATTERN: OnceLock<Regex> = OnceLock::new();
         let pattern = PATTERN.get_or_init(|| {
-            Regex::new(r"^(?<host>github|gitlab|bitbucket):(?<owner>[a-zA-Z0-9._-]+)\/(?<repo>[a-zA-Z0-9._-]+)(\/(?<path>[^\/]+(\/[^\/]+)*))?$").unwrap()
+            Regex::new(r"^(?<host>github|gitlab|bitbucket):(?<owner>[a-zA-Z0-9._-]+)\/(?<repo>[a-zA-Z0-9._-]+)(@(?<ref>[^\/@]+))?(\/(?<path>[^\/]+(\/[^\/]+)*))?$").unwrap()
+        });
+        // `//` separates the repository from the path inside it,
+        // so that the owner may consist of nested groups (gitlab only)
+        let nested_pattern = NESTED_PATTERN.get_or_init(|| {
+            Regex::new(r"^(?<host>github|gitlab|bitbucket):(?<owner>[a-zA-Z0-9._-]+(\/[a-zA-Z0-9._-]+)*)\/(?<repo>[a-zA-Z0-9._-]+)(@(?<ref>[^\/@]+))?\/\/(?<path>[^\/]+(\/[^\/]+)*)?$").unwrap()
+        });
+        // e.g. `git@github.com:owner/repo.git` or `ssh://git@host:22/owner/repo.git`,
+        // optionally followed by `@<ref>` and `//<path>`
+        let ssh_pattern = SSH_PATTERN.get_or_init(|| {
+            Regex::new(r"^(?<url>(ssh:\/\/([a-zA-Z0-9._-]+@)?(?<ssh_domain>[a-zA-Z0-9.-]+)(:[0-9]+)?\/|[a-zA-Z0-9._-]+@(?<scp_domain>[a-zA-Z0-9.-]+):)(?<owner>[a-zA-Z0-9._-]+(\/[a-zA-Z0-9._-]+)*)\/(?<repo>[a-zA-Z0-9._-]+?)(\.git)?)(@(?<ref>[^\/@]+))?(\/\/(?<path>[^\/]+(\/[^\/]+)*))?$").unwrap()
         });
+        if let Some(captures) = ssh_pattern.captures(s) {
+            let domain = captures
+                .name("ssh_domain")
+                .or(captures.name("scp_domain"))
+                .unwrap()
+                .as_str();
+            return Ok(Self {
+                host: Host::from_domain(domain),
+                owner: captures.name("owner").unwrap().as_str().to_string(),
+                repo: captures.name("repo").unwrap().as_str().to_string(),
+                reference: captures.name("ref").map(|m| m.as_str().to_string()),
+                path: captures
+                    .name("path")
+                    .map(|m| m.as_str().split('/').collect()),
+                ssh: Some(captures.name("url").unwrap().as_str().to_string()),
+            });
+        }
+        if let Some(captures) = nested_pattern.captures(s) {
+            let host = Host::from_str(captures.name("host").unwrap().as_str()).unwrap();
+            let owner = captures.name("owner").unwrap().as_str();
+            if owner.contains('/') && host != Host::GitLab {
+                bail!("nested groups are only supported by gitlab")
+            }
+            return Ok(Self {
+                host,
+                owner: owner.to_string(),
+                repo: captures.name("repo").unwrap().as_str().to_string(),
+                reference: captures.name("ref").map(|m| m.as_str().to_string()),
+                path: captures
+                    .name("path")
+                    .map(|m| m.as_str().split('/').collect()),
+                ssh: None,
+            });
+        }
         if let Some(captures) = pattern.captures(s) {
             return Ok(Self {
                 host: Host::from_str(captures.name("host").unwrap().as_str()).unwrap(),
                 owner: captures.name("owner").unwrap().as_str().to_string(),
                 repo: captures.name("repo").unwrap().as_str().to_string(),
+                reference: captures.name("ref").map(|m| m.as_str().to_string()),
                 path: captures
                     .name("path")
                     .map(|m| m.as_str().split('/').collect()),
+                ssh: None,
             });
         }
         bail!("mismatched git source pattern")
@@ -76,29 +166,197 @@ impl FromStr for Source {
 }
 
 impl Source {
-    pub(crate) fn resolve(&self, prefix: impl AsRef<Path>) -> Result<PathBuf> {
+    /// Shorthand of the source, which parses back to the same source.
+    pub(crate) fn shorthand(&self) -> String {
+        if let Some(url) = &self.ssh {
+            let mut shorthand = url.clone();
+            if let Some(reference) = &self.reference {
+                shorthand.push_str(&format!("@{reference}"));
+            }
+            if let Some(path) = &self.path {
+                let path = path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                shorthand.push_str(&format!("//{path}"));
+            }
+            return shorthand;
+        }
+        let mut shorthand = format!("{}:{}/{}", self.host.name(), self.owner, self.dirname());
+        let separator = if self.owner.contains('/') { "//" } else { "/" };
+        match &self.path {
+            Some(path) => {
+                let path = path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                shorthand.push_str(separator);
+                shorthand.push_str(&path);
+            }
+            None if self.owner.contains('/') => shorthand.push_str("//"),
+            None => {}
+        }
+        shorthand
+    }
+
+    /// Name of the cached repository; each ref is cached separately.
+    fn dirname(&self) -> String {
+        match &self.reference {
+            Some(reference) => format!("{}@{reference}", self.repo),
+            None => self.repo.clone(),
+        }
+    }
+
+    /// Path of the cached repository, keyed by its host and full namespace.
+    fn repository_path(&self, prefix: impl AsRef<Path>) -> PathBuf {
+        prefix
+            .as_ref()
+            .join(self.host.name())
+            .join(&self.owner)
+            .join(self.dirname())
+    }
+
+    /// Path of the template inside the cached repository.
+    pub(crate) fn local_path(&self, prefix: impl AsRef<Path>) -> PathBuf {
+        let mut path = self.repository_path(prefix);
+        if let Some(subpath) = &self.path {
+            path.push(subpath);
+        }
+        path
+    }
+
+    /// Cleans up leftovers of interrupted or broken clones,
+    /// returning the paths of the repository and its partial clone.
+    fn prepare(&self, prefix: impl AsRef<Path>) -> Result<(PathBuf, PathBuf)> {
         if !git::check_installed()? {
             bail!("git is not installed; required for git source")
         }
-        let mut dst = prefix.as_ref().join(&self.owner).join(&self.repo);
+        let dst = self.repository_path(&prefix);
+        // groups and repositories share the namespace, so neither may lie inside the other
+        if let Some(repository) = dst
+            .ancestors()
+            .skip(1)
+            .take_while(|path| *path != prefix.as_ref())
+            .find(|path| path.join(".git").exists())
+        {
+            bail!(
+                "cannot cache repository inside cached repository: '{}'",
+                repository.display()
+            )
+        }
+        if dst.is_dir() && !dst.join(".git").exists() && holds_repositories(&dst) {
+            bail!(
+                "cannot cache repository over cached repositories of its groups: '{}'",
+                dst.display()
+            )
+        }
+        let partial = dst.with_file_name(format!(".{}.partial", self.dirname()));
+        if partial.exists() {
+            say!("Cleaning up interrupted clone: '{}'", partial.display());
+            fs::remove_dir_all(&partial).context(format!(
+                "failed to remove partial clone: '{}'",
+                partial.display()
+            ))?;
+        }
+        // only a missing or corrupt `.git` is cleaned up, as git also fails on healthy repositories
+        // for reasons of its own (e.g. `safe.directory`), which are reported instead
+        if dst.exists() && !dst.join(".git").join("HEAD").is_file() {
+            say!("Repository is broken: '{}'", dst.display());
+            fs::remove_dir_all(&dst).context(format!(
+                "failed to remove broken repository: '{}'",
+                dst.display()
+            ))?;
+        } else if dst.exists() {
+            Repository::new(&dst).head().context(format!(
+                "failed to read cached repository: '{}'",
+                dst.display()
+            ))?;
+        }
+        Ok((dst, partial))
+    }
+
+    fn clone_into(&self, dst: &Path, partial: &Path, quiet: bool) -> Result<()> {
+        static COMMIT: OnceLock<Regex> = OnceLock::new();
+        let commit = COMMIT.get_or_init(|| Regex::new(r"^[0-9a-f]{7,40}$").unwrap());
+        // only branches and tags can be cloned shallowly, commits need the full history
+        let shallow = SHALLOW.load(Ordering::Relaxed)
+            && !self.reference.as_ref().is_some_and(|r| commit.is_match(r));
+        let branch = self.reference.as_deref().filter(|_| shallow);
+        let repository =
+            Repository::clone_with_retry(self, partial, shallow, branch, CLONE_ATTEMPTS, quiet)?;
+        if let Some(reference) = &self.reference {
+            repository.checkout(reference, quiet)?;
+        }
+        fs::rename(partial, dst).context(format!(
+            "failed to move cloned repository to '{}'",
+            dst.display()
+        ))?;
+        Ok(())
+    }
+
+    /// Clones or fast-forwards the repository without prompting or progress output.
+    pub(crate) fn fetch(&self, prefix: impl AsRef<Path>) -> Result<Fetched> {
+        let (dst, partial) = self.prepare(prefix)?;
+        if !dst.exists() {
+            self.clone_into(&dst, &partial, true)?;
+            return Ok(Fetched::Cloned);
+        }
+        let repository = Repository::new(&dst);
+        if let Some(pin) = repository.get_config("tapgen.pin")? {
+            return Ok(Fetched::Pinned(pin));
+        }
+        if repository
+            .check_fastforwardable()
+            .context("failed to check if git repository is fast-forwardable")?
+        {
+            repository.pull(true)?;
+            return Ok(Fetched::Updated);
+        }
+        Ok(Fetched::UpToDate)
+    }
+
+    /// Clones the repository unless it is cached, without prompting or progress output,
+    /// returning whether it was cloned.
+    pub(crate) fn prefetch(&self, prefix: impl AsRef<Path>) -> Result<bool> {
+        let (dst, partial) = self.prepare(prefix)?;
+        if dst.exists() {
+            return Ok(false);
+        }
+        self.clone_into(&dst, &partial, true)?;
+        Ok(true)
+    }
+
+    pub(crate) fn resolve(&self, prefix: impl AsRef<Path>) -> Result<PathBuf> {
+        let (mut dst, partial) = self.prepare(prefix)?;
         if dst.exists() {
-            println!("Repository already exists: '{}'", dst.display());
-            println!("Checking for updates...");
+            say!("Repository already exists: '{}'", dst.display());
+            say!("Checking for updates...");
             let repository = Repository::new(&dst);
-            if repository
+            if let Some(pin) = repository.get_config("tapgen.pin")? {
+                say!("Repository is pinned at {pin}.");
+            } else if repository
                 .check_fastforwardable()
                 .context("failed to check if git repository is fast-forwardable")?
             {
                 if prompt::confirm("Outdated. Pull to update?", Some(true)) {
-                    repository.pull()?;
+                    repository.pull(false)?;
                 }
             } else {
-                println!("Repository is up to date.");
+                say!("Repository is up to date.");
             }
         } else {
-            Repository::clone(self, &dst)?;
+            self.clone_into(&dst, &partial, false)?;
         }
-        println!();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0);
+        if let Err(err) = Repository::new(&dst).set_config(LAST_USED_KEY, &now.to_string()) {
+            log::warn!("cannot record last use of '{}': {err:#}", dst.display());
+        }
+        say!();
         if let Some(path) = &self.path {
             dst.push(path);
         }
@@ -106,6 +364,55 @@ impl Source {
     }
 }
 
+const CLONE_ATTEMPTS: u32 = 3;
+
+/// Whether the clone failed for a reason that may go away by retrying, e.g. a dropped connection,
+/// unlike a missing repository or failed authentication.
+fn is_transient(err: &Error) -> bool {
+    const TRANSIENT: [&str; 10] = [
+        "could not resolve host",
+        "timed out",
+        "timeout",
+        "early eof",
+        "connection reset",
+        "connection refused",
+        "unexpected disconnect",
+        "the remote end hung up",
+        "rpc failed",
+        "network is unreachable",
+    ];
+    let message = err.to_string().to_lowercase();
+    TRANSIENT.iter().any(|reason| message.contains(reason))
+}
+
+/// Git config key of the time (in unix seconds) a cached repository was last generated from.
+pub(crate) const LAST_USED_KEY: &str = "tapgen.lastused";
+
+static SHALLOW: AtomicBool = AtomicBool::new(true);
+
+/// Sets whether repositories are cloned shallowly (`--depth 1`).
+pub(crate) fn set_shallow(shallow: bool) {
+    SHALLOW.store(shallow, Ordering::Relaxed);
+}
+
+pub(crate) enum Fetched {
+    Cloned,
+    Updated,
+    UpToDate,
+    Pinned(String),
+}
+
+impl std::fmt::Display for Fetched {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Cloned => write!(f, "cloned"),
+            Self::Updated => write!(f, "updated"),
+            Self::UpToDate => write!(f, "up to date"),
+            Self::Pinned(pin) => write!(f, "pinned at {pin}"),
+        }
+    }
+}
+
 pub(crate) struct Repository(PathBuf);
 
 impl Repository {
@@ -113,22 +420,98 @@ impl Repository {
         Self(path.as_ref().to_path_buf())
     }
 
-    pub(crate) fn clone(src: impl ToString, dst: impl AsRef<Path>) -> Result<Self> {
-        let status = Command::new("git")
+    pub(crate) fn clone(
+        src: impl ToString,
+        dst: impl AsRef<Path>,
+        shallow: bool,
+        branch: Option<&str>,
+        quiet: bool,
+    ) -> Result<Self> {
+        log::info!(
+            "cloning '{}' into '{}'{}",
+            src.to_string(),
+            dst.as_ref().display(),
+            if shallow { " (shallow)" } else { "" }
+        );
+        let mut child = Command::new("git")
             .arg("clone")
+            .arg(if quiet { "--quiet" } else { "--progress" })
+            .args(shallow.then_some(["--depth", "1"]).into_iter().flatten())
+            .args(
+                branch
+                    .map(|branch| ["--branch", branch])
+                    .into_iter()
+                    .flatten(),
+            )
             .arg(src.to_string())
             .arg(dst.as_ref())
-            .status()
+            .stderr(Stdio::piped())
+            .spawn()
             .context("failed to execute git clone command")?;
+        // the messages are passed through, and kept to tell the reason of a failure
+        let mut messages = Vec::new();
+        let mut pipe = child.stderr.take().unwrap();
+        let mut buf = [0; 4096];
+        loop {
+            let n = pipe.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            io::stderr().write_all(&buf[..n])?;
+            messages.extend_from_slice(&buf[..n]);
+        }
+        let status = child.wait()?;
         if !status.success() {
-            bail!("failed to clone git repository ({status})")
+            let messages = String::from_utf8_lossy(&messages);
+            let reasons = messages
+                .lines()
+                .filter(|line| line.starts_with("fatal:") || line.starts_with("error:"))
+                .collect::<Vec<_>>()
+                .join("; ");
+            bail!("failed to clone git repository ({status}): {reasons}")
         }
         Ok(Self(dst.as_ref().to_path_buf()))
     }
 
-    pub(crate) fn pull(&self) -> Result<()> {
+    pub(crate) fn clone_with_retry(
+        src: impl ToString,
+        dst: impl AsRef<Path>,
+        shallow: bool,
+        branch: Option<&str>,
+        attempts: u32,
+        quiet: bool,
+    ) -> Result<Self> {
+        let dst = dst.as_ref();
+        let mut attempt = 1;
+        loop {
+            match Self::clone(src.to_string(), dst, shallow, branch, quiet) {
+                Ok(repository) => return Ok(repository),
+                Err(err) if attempt < attempts && is_transient(&err) => {
+                    let backoff = Duration::from_secs(1 << attempt);
+                    say!(
+                        "Clone failed ({err}); retrying in {}s ({attempt}/{attempts})...",
+                        backoff.as_secs()
+                    );
+                    log::warn!("clone attempt {attempt} failed: {err}");
+                    if dst.exists() {
+                        fs::remove_dir_all(dst).context(format!(
+                            "failed to remove partial clone: '{}'",
+                            dst.display()
+                        ))?;
+                    }
+                    thread::sleep(backoff);
+                    attempt += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    pub(crate) fn pull(&self, quiet: bool) -> Result<()> {
+        log::info!("pulling '{}'", self.0.display());
         let status = Command::new("git")
             .arg("pull")
+            .args(quiet.then_some("--quiet"))
             .current_dir(&self.0)
             .status()
             .context("failed to execute git pull command")?;
@@ -138,6 +521,107 @@ impl Repository {
         Ok(())
     }
 
+    pub(crate) fn checkout(&self, reference: &str, quiet: bool) -> Result<()> {
+        log::info!("checking out '{reference}' in '{}'", self.0.display());
+        let status = Command::new("git")
+            .arg("checkout")
+            .args(quiet.then_some("--quiet"))
+            .arg(reference)
+            .current_dir(&self.0)
+            .status()
+            .context("failed to execute git checkout command")?;
+        if !status.success() {
+            bail!("failed to check out '{reference}' ({status})")
+        }
+        Ok(())
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Top-level directory of the working tree containing the repository path.
+    pub(crate) fn toplevel(&self) -> Result<PathBuf> {
+        match self.output(&["rev-parse", "--show-toplevel"])? {
+            Some(toplevel) => Ok(PathBuf::from(toplevel)),
+            None => bail!("failed to resolve top-level directory of git repository"),
+        }
+    }
+
+    /// Writes the tree of `reference` into `dst`, leaving the working tree untouched.
+    pub(crate) fn export(&self, reference: &str, dst: impl AsRef<Path>) -> Result<()> {
+        log::info!("exporting '{reference}' of '{}'", self.0.display());
+        let command = Command::new("git")
+            .args(["archive", "--format=tar", reference])
+            .current_dir(&self.0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .context("failed to execute git archive command")?;
+        if !command.status.success() {
+            bail!("failed to export '{reference}' ({})", command.status)
+        }
+        tar::Archive::new(command.stdout.as_slice())
+            .unpack(dst)
+            .context(format!("failed to unpack '{reference}'"))
+    }
+
+    fn output(&self, args: &[&str]) -> Result<Option<String>> {
+        let command = Command::new("git")
+            .args(args)
+            .current_dir(&self.0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .context(format!("failed to execute git {} command", args[0]))?;
+        if !command.status.success() {
+            return Ok(None);
+        }
+        let output = String::from_utf8(command.stdout)
+            .expect("command output encoding should be utf-8")
+            .trim()
+            .to_string();
+        Ok(Some(output))
+    }
+
+    pub(crate) fn head(&self) -> Result<String> {
+        match self.output(&["rev-parse", "HEAD"])? {
+            Some(head) => Ok(head),
+            None => bail!("failed to resolve HEAD of git repository"),
+        }
+    }
+
+    pub(crate) fn is_worktree(&self) -> Result<bool> {
+        Ok(self
+            .output(&["rev-parse", "--is-inside-work-tree"])?
+            .as_deref()
+            == Some("true"))
+    }
+
+    pub(crate) fn is_dirty(&self) -> Result<bool> {
+        Ok(self
+            .output(&["status", "--porcelain"])?
+            .is_some_and(|status| !status.is_empty()))
+    }
+
+    pub(crate) fn get_config(&self, name: &str) -> Result<Option<String>> {
+        self.output(&["config", "--local", "--get", name])
+    }
+
+    pub(crate) fn set_config(&self, name: &str, value: &str) -> Result<()> {
+        if self.output(&["config", "--local", name, value])?.is_none() {
+            bail!("failed to set git config: '{name}'")
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unset_config(&self, name: &str) -> Result<()> {
+        self.output(&["config", "--local", "--unset", name])?;
+        Ok(())
+    }
+
     pub(crate) fn check_fastforwardable(&self) -> Result<bool> {
         let status = Command::new("git")
             .arg("remote")
@@ -0,0 +1,211 @@
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::Result;
+use clap::Args;
+use regex::Regex;
+use serde_json::{json, Map, Value as JsonValue};
+
+use tapgen::template::Template;
+use tapgen::variable::{Variable, VariableValue};
+
+use crate::config::Config;
+use crate::source::{self, Source};
+
+#[derive(Clone, Args)]
+pub(crate) struct Info {
+    #[arg(
+        help = "Source of template to be inspected.",
+        value_parser = Source::from_str,
+    )]
+    src: Source,
+    #[arg(
+        long = "schema",
+        help = "Print a JSON Schema describing the answers of the template."
+    )]
+    schema: bool,
+}
+
+impl Info {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let path = self.src.resolve(&config.prefix)?;
+        let template = source::load_template(&path, &config.prefix)?;
+        if self.schema {
+            let schema = answers_schema(&template);
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
+        }
+        let metadata = &template.metadata;
+        println!("{} by {}", metadata.name, metadata.author);
+        if let Some(description) = &metadata.description {
+            println!("{description}");
+        }
+        if let Some(url) = &metadata.url {
+            println!("> {url}");
+        }
+        println!("=> '{}'", template.root.display());
+        println!();
+        println!("[Variables]");
+        for (name, variable) in &template.variables {
+            print!(
+                "│ {name} ({}): {}",
+                variable_kind(variable),
+                variable.prompt
+            );
+            if let Some(condition) = &variable.condition {
+                print!(" [if {}]", condition.as_str());
+            }
+            if let Some(group) = &variable.group {
+                print!(" [in {group}]");
+            }
+            println!();
+        }
+        Ok(())
+    }
+}
+
+fn variable_kind(variable: &Variable) -> &'static str {
+    match &variable.value {
+        VariableValue::String { .. } => "string",
+        VariableValue::Array { .. } => "array",
+        VariableValue::Integer { .. } => "integer",
+        VariableValue::Boolean { .. } => "boolean",
+        VariableValue::Path { .. } => "path",
+        VariableValue::Date { .. } => "date",
+        VariableValue::Map { .. } => "map",
+    }
+}
+
+fn variable_schema(variable: &Variable) -> Map<String, JsonValue> {
+    let mut schema = Map::new();
+    schema.insert(String::from("description"), json!(variable.prompt));
+    match &variable.value {
+        VariableValue::String {
+            default,
+            pattern,
+            choices,
+            ..
+        } => {
+            schema.insert(String::from("type"), json!("string"));
+            if !default.is_empty() {
+                schema.insert(String::from("default"), json!(default));
+            }
+            if let Some(pattern) = pattern {
+                schema.insert(String::from("pattern"), json!(pattern.as_str()));
+            }
+            if let Some(choices) = choices {
+                let values = choices.iter().map(|c| &c.value).collect::<Vec<_>>();
+                schema.insert(String::from("enum"), json!(values));
+            }
+        }
+        VariableValue::Array {
+            default,
+            choices,
+            allow_custom,
+            min,
+            max,
+        } => {
+            schema.insert(String::from("type"), json!("array"));
+            schema.insert(String::from("default"), json!(default));
+            schema.insert(String::from("uniqueItems"), json!(true));
+            if let Some(min) = min {
+                schema.insert(String::from("minItems"), json!(min));
+            }
+            if let Some(max) = max {
+                schema.insert(String::from("maxItems"), json!(max));
+            }
+            if *allow_custom {
+                schema.insert(String::from("items"), json!({ "type": "string" }));
+            } else {
+                let values = choices.iter().map(|c| &c.value).collect::<Vec<_>>();
+                schema.insert(
+                    String::from("items"),
+                    json!({ "type": "string", "enum": values }),
+                );
+            }
+        }
+        VariableValue::Integer { default, range } => {
+            schema.insert(String::from("type"), json!("integer"));
+            schema.insert(String::from("default"), json!(default));
+            if let Some((min, max)) = range {
+                schema.insert(String::from("minimum"), json!(min));
+                schema.insert(String::from("maximum"), json!(max));
+            }
+        }
+        VariableValue::Boolean { default, .. } => {
+            schema.insert(String::from("type"), json!("boolean"));
+            schema.insert(String::from("default"), json!(default));
+        }
+        VariableValue::Path { default, kind, .. } => {
+            schema.insert(String::from("type"), json!("string"));
+            schema.insert(String::from("x-path-kind"), json!(kind.to_string()));
+            if !default.is_empty() {
+                schema.insert(String::from("default"), json!(default));
+            }
+        }
+        VariableValue::Date { default, format } => {
+            schema.insert(String::from("type"), json!("string"));
+            schema.insert(String::from("x-date-format"), json!(format));
+            schema.insert(String::from("default"), json!(default));
+        }
+        VariableValue::Map { fields } => {
+            let properties = fields
+                .iter()
+                .map(|(name, field)| (name.clone(), JsonValue::Object(variable_schema(field))))
+                .collect::<Map<_, _>>();
+            schema.insert(String::from("type"), json!("object"));
+            schema.insert(String::from("properties"), JsonValue::Object(properties));
+            schema.insert(String::from("additionalProperties"), json!(false));
+        }
+    }
+    schema
+}
+
+fn answers_schema(template: &Template) -> JsonValue {
+    static IDENTIFIER: OnceLock<Regex> = OnceLock::new();
+    let identifier = IDENTIFIER.get_or_init(|| Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap());
+
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    let mut dependencies = Map::new();
+    for (name, variable) in &template.variables {
+        let mut schema = variable_schema(variable);
+        if let Some(condition) = &variable.condition {
+            schema.insert(String::from("x-condition"), json!(condition.as_str()));
+            let mut depends = identifier
+                .find_iter(condition.as_str())
+                .map(|m| m.as_str())
+                .filter(|ident| *ident != name.as_str() && template.variables.contains_key(*ident))
+                .collect::<Vec<_>>();
+            depends.sort_unstable();
+            depends.dedup();
+            for dependency in depends {
+                dependencies
+                    .entry(dependency)
+                    .or_insert_with(|| json!([]))
+                    .as_array_mut()
+                    .unwrap()
+                    .push(json!(name));
+            }
+        } else {
+            required.push(name);
+        }
+        properties.insert(name.clone(), JsonValue::Object(schema));
+    }
+
+    let mut schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": template.metadata.name,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": false,
+    });
+    if let Some(description) = &template.metadata.description {
+        schema["description"] = json!(description);
+    }
+    if !dependencies.is_empty() {
+        schema["x-dependencies"] = JsonValue::Object(dependencies);
+    }
+    schema
+}
@@ -0,0 +1,130 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{bail, Context as _, Result};
+use clap::Args;
+
+use crate::git;
+use crate::prompt;
+
+const BASE: &str = "{{ project_name }}";
+
+const BEFORE_HOOK: &str = r#"#!/bin/sh
+# Runs in the template directory before prompting.
+echo "Hello from the before hook!"
+"#;
+
+const AFTER_HOOK: &str = r#"#!/bin/sh
+# Rendered as a template, then runs in the generated output before applying.
+echo "Generated {{ project_name }}!"
+"#;
+
+#[derive(Clone, Args)]
+pub(crate) struct Init {
+    #[arg(help = "Directory of the new template (default: current directory).")]
+    dir: Option<PathBuf>,
+}
+
+fn quote(s: &str) -> String {
+    toml::Value::from(s).to_string()
+}
+
+impl Init {
+    pub(crate) fn run(&self) -> Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir().context("failed to locate current directory")?,
+        };
+        let definition = dir.join("tapgen.toml");
+        if definition.exists() {
+            bail!("template already exists: '{}'", definition.display())
+        }
+        let dirname = dir
+            .canonicalize()
+            .unwrap_or(dir.clone())
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let author = if git::check_installed()? {
+            git::obtain_config()?.remove("name").unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let name = prompt::input(
+            "Template name",
+            Some(dirname),
+            None::<fn(&String) -> Result<()>>,
+        );
+        let author = prompt::input("Author", Some(author), None::<fn(&String) -> Result<()>>);
+        let description = prompt::input(
+            "Description",
+            Some(String::new()),
+            None::<fn(&String) -> Result<()>>,
+        );
+        let hooks = prompt::boolean("Create example hook scripts?", false);
+
+        let mut contents = format!(
+            "__name__ = {}\n__author__ = {}\n",
+            quote(&name),
+            quote(&author)
+        );
+        if !description.is_empty() {
+            contents.push_str(&format!("__description__ = {}\n", quote(&description)));
+        }
+        contents.push_str(&format!(
+            r#"__base__ = "./{BASE}"
+
+[project_name]
+prompt = "Project name"
+default = "my-project"
+pattern = "^[a-z][a-z0-9-]*$"
+
+[license]
+prompt = "License"
+default = "MIT"
+choices = ["MIT", "Apache-2.0", "GPL-3.0"]
+
+[docs]
+prompt = "Include documentation?"
+default = true
+"#
+        ));
+
+        let base = dir.join(BASE);
+        fs::create_dir_all(&base).context(format!(
+            "failed to create base directory: '{}'",
+            base.display()
+        ))?;
+        fs::write(&definition, contents).context(format!(
+            "failed to write template: '{}'",
+            definition.display()
+        ))?;
+        fs::write(
+            base.join("README.md"),
+            "# {{ project_name }}\n\nLicensed under {{ license }}.\n",
+        )
+        .context("failed to write example file")?;
+        if hooks {
+            for (name, script) in [
+                ("tapgen.before.hook", BEFORE_HOOK),
+                ("tapgen.after.hook", AFTER_HOOK),
+            ] {
+                let path = dir.join(name);
+                fs::write(&path, script)
+                    .context(format!("failed to write hook script: '{}'", path.display()))?;
+                #[cfg(unix)]
+                {
+                    use std::os::unix::fs::PermissionsExt as _;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).context(
+                        format!("failed to set hook script permission: '{}'", path.display()),
+                    )?;
+                }
+            }
+        }
+        println!();
+        println!("Successfully created template!");
+        println!("=> '{}'", definition.display());
+        println!("Try it out with `tapgen --local {}`.", dir.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::generate::canonicalize_lenient;
+use crate::pack::sha256_hex;
+
+/// Directory under the prefix of the journals of the last applies, keyed by destination.
+const JOURNALS_DIR: &str = ".journals";
+
+const JOURNAL_FILE: &str = "journal.toml";
+
+/// Checksum of the contents of a file, or of the target of a symlink.
+pub(crate) fn checksum(path: &Path) -> io::Result<String> {
+    match fs::read_link(path) {
+        Ok(target) => Ok(sha256_hex(target.as_os_str().as_encoded_bytes())),
+        Err(_) => fs::read(path).map(|contents| sha256_hex(&contents)),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum Action {
+    Created,
+    CreatedDir,
+    Overwritten, // the previous contents being kept in the journal directory
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub(crate) path: PathBuf, // relative to the destination
+    pub(crate) action: Action,
+    pub(crate) sha256: Option<String>, // of the applied contents of a file
+}
+
+/// Files created and overwritten while applying output, so that the apply can be undone.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Journal {
+    #[serde(skip)]
+    dst: PathBuf,
+    #[serde(skip)]
+    dir: PathBuf, // of the journal under the prefix
+    pub(crate) entries: Vec<Entry>,
+}
+
+impl Journal {
+    pub(crate) fn new(prefix: impl AsRef<Path>, dst: impl AsRef<Path>) -> Self {
+        Self {
+            dst: dst.as_ref().to_path_buf(),
+            dir: Self::dir(prefix, dst),
+            entries: Vec::new(),
+        }
+    }
+
+    fn dir(prefix: impl AsRef<Path>, dst: impl AsRef<Path>) -> PathBuf {
+        let dst = canonicalize_lenient(dst.as_ref());
+        prefix
+            .as_ref()
+            .join(JOURNALS_DIR)
+            .join(&sha256_hex(dst.as_os_str().as_encoded_bytes())[..16])
+    }
+
+    /// Path of the previous contents of an overwritten file.
+    pub(crate) fn original(&self, name: &Path) -> PathBuf {
+        self.dir.join("files").join(name)
+    }
+
+    /// Discards the journal of the previous apply, which can no longer be undone.
+    pub(crate) fn clear(&self) -> Result<()> {
+        let dir = &self.dir;
+        if dir.exists() {
+            fs::remove_dir_all(&dir)
+                .context(format!("failed to remove journal: '{}'", dir.display()))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn created(&mut self, name: &Path) {
+        self.push(name, Action::Created);
+    }
+
+    pub(crate) fn created_dir(&mut self, name: &Path) {
+        self.push(name, Action::CreatedDir);
+    }
+
+    /// Keeps the contents of the file at `name` before it is overwritten.
+    pub(crate) fn overwriting(&mut self, name: &Path) -> Result<()> {
+        let original = self.original(name);
+        if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(self.dst.join(name), &original)
+            .context(format!("failed to journal file: '{}'", name.display()))?;
+        self.push(name, Action::Overwritten);
+        Ok(())
+    }
+
+    fn push(&mut self, name: &Path, action: Action) {
+        self.entries.push(Entry {
+            path: name.to_path_buf(),
+            action,
+            sha256: None,
+        });
+    }
+
+    /// Saves the journal along with the checksums of the applied files,
+    /// those of a failed apply missing where a file was not written.
+    pub(crate) fn save(mut self) -> Result<()> {
+        if self.entries.is_empty() {
+            return Ok(());
+        }
+        for entry in &mut self.entries {
+            if entry.action != Action::CreatedDir {
+                entry.sha256 = checksum(&self.dst.join(&entry.path)).ok();
+            }
+        }
+        let dir = &self.dir;
+        fs::create_dir_all(dir)?;
+        let path = dir.join(JOURNAL_FILE);
+        fs::write(&path, toml::to_string_pretty(&self)?)
+            .context(format!("failed to write journal: '{}'", path.display()))?;
+        log::info!("saved journal of {} entries", self.entries.len());
+        Ok(())
+    }
+
+    pub(crate) fn load(prefix: impl AsRef<Path>, dst: impl AsRef<Path>) -> Result<Self> {
+        let dir = Self::dir(prefix, &dst);
+        let path = dir.join(JOURNAL_FILE);
+        let contents = fs::read_to_string(&path)
+            .context(format!("no apply to undo in '{}'", dst.as_ref().display()))?;
+        let mut journal = toml::from_str::<Self>(&contents)
+            .context(format!("failed to parse journal: '{}'", path.display()))?;
+        journal.dst = dst.as_ref().to_path_buf();
+        journal.dir = dir;
+        Ok(journal)
+    }
+}
@@ -1,5 +1,10 @@
+mod attributes;
+mod cookiecutter;
+mod copier;
+mod functions;
 mod utils;
 
+pub mod coerce;
 pub mod metadata;
 pub mod template;
 pub mod variable;
@@ -0,0 +1,45 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write as _;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context as _, Result};
+use chrono::Local;
+use log::{LevelFilter, Log, Metadata, Record};
+
+struct FileLogger(Mutex<File>);
+
+impl Log for FileLogger {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        let mut file = self.0.lock().unwrap();
+        let _ = writeln!(
+            file,
+            "{} {:<5} [{}] {}",
+            Local::now().format("%Y-%m-%dT%H:%M:%S%.3f"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn flush(&self) {
+        let _ = self.0.lock().unwrap().flush();
+    }
+}
+
+pub(crate) fn init(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context(format!("failed to open log file: '{}'", path.display()))?;
+    log::set_boxed_logger(Box::new(FileLogger(Mutex::new(file))))
+        .context("failed to install logger")?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
@@ -1,17 +1,58 @@
+#[macro_use]
+mod porcelain;
+
+mod answers;
+mod archive;
+mod cache;
+mod check;
 mod config;
 mod copy;
+mod diff;
+mod fuzz;
 mod generate;
+mod get;
 mod git;
+mod info;
+mod init;
+mod journal;
+mod logger;
+mod merge;
+mod net;
+mod pack;
 mod prefix;
 mod prompt;
+mod replay;
+mod secret;
+mod selfupdate;
+mod shell;
+mod source;
+mod stats;
+mod test;
+mod undo;
+mod upgrade;
+mod workspace;
 
 use std::fs;
+use std::path::PathBuf;
 
-use anyhow::Result;
-use clap::{ArgAction, Parser};
+use anyhow::{Context as _, Result};
+use clap::{ArgAction, Parser, Subcommand};
 
+use crate::cache::{Cache, List, Pin, Remove, Update};
+use crate::check::Check;
 use crate::config::Config;
+use crate::fuzz::Fuzz;
 use crate::generate::Generate;
+use crate::get::Get;
+use crate::info::Info;
+use crate::init::Init;
+use crate::pack::Pack;
+use crate::replay::Replay;
+use crate::selfupdate::SelfUpdate;
+use crate::stats::Stats;
+use crate::test::Test;
+use crate::undo::Undo;
+use crate::upgrade::Upgrade;
 
 #[derive(Parser)]
 #[command(version)]
@@ -19,11 +60,31 @@ use crate::generate::Generate;
 #[command(disable_help_flag = true)]
 #[command(disable_version_flag = true)]
 #[command(arg_required_else_help = true)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(subcommand_negates_reqs = true)]
 #[command(about = "Tony's Almighty Project Generator")]
 #[command(author = "Tony Chan <tnychn@protonmail.com>")]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     #[command(flatten)]
-    generate: Generate,
+    generate: Option<Generate>,
+
+    #[arg(
+        long = "log-file",
+        global = true,
+        value_name = "PATH",
+        help = "Write detailed logs to the given file."
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        long = "full-clone",
+        global = true,
+        help = "Clone git sources with their full history instead of shallowly."
+    )]
+    full_clone: bool,
 
     #[arg(
         short = 'h',
@@ -42,10 +103,82 @@ struct Cli {
     version: Option<bool>,
 }
 
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Create a new template interactively.")]
+    Init(Init),
+    #[command(about = "Show information about a template.")]
+    Info(Info),
+    #[command(about = "Fetch templates into the prefix concurrently.")]
+    Get(Get),
+    #[command(about = "List templates cached under the prefix.")]
+    List(List),
+    #[command(about = "Remove cached repositories matching the patterns.")]
+    Remove(Remove),
+    #[command(about = "Pin cached repositories matching the patterns at their current revision.")]
+    Pin(Pin),
+    #[command(about = "Fast-forward cached repositories matching the patterns.")]
+    Update(Update),
+    #[command(about = "Inspect and manage the cache under the prefix.")]
+    Cache(Cache),
+    #[command(about = "Check a template for common mistakes.")]
+    Check(Check),
+    #[command(about = "Show statistics about the composition of a template.")]
+    Stats(Stats),
+    #[command(about = "Run the tests defined in a template.")]
+    Test(Test),
+    #[command(about = "Generate from a template with random answers to find failures.")]
+    Fuzz(Fuzz),
+    #[command(about = "Pack a template into a distributable archive.")]
+    Pack(Pack),
+    #[command(about = "Replay a previous generation from its saved answers.")]
+    Replay(Replay),
+    #[command(about = "Update a generated project from a newer version of its template.")]
+    Upgrade(Upgrade),
+    #[command(about = "Undo the last apply of output to a destination.")]
+    Undo(Undo),
+    #[command(about = "Update tapgen to the latest release.")]
+    SelfUpdate(SelfUpdate),
+}
+
 fn main() -> Result<()> {
+    // sources are parsed along with the arguments, so aliases must be known beforehand,
+    // but a broken config must not get in the way of --help and --version
+    let config = Config::init();
+    if let Ok(config) = &config {
+        source::set_aliases(config.aliases.clone());
+    }
     let cli = Cli::parse();
-    let config = Config::init().expect("failed to initialize config");
-    fs::create_dir_all(&config.prefix).expect("failed to create prefix directory");
+    let config = config.context("failed to initialize config")?;
+    porcelain::set_enabled(cli.generate.as_ref().is_some_and(Generate::is_porcelain));
+    Config::install().context("failed to write config")?;
+    fs::create_dir_all(&config.prefix).context("failed to create prefix directory")?;
+    if let Some(path) = cli.log_file.as_ref().or(config.log_file.as_ref()) {
+        logger::init(path).context("failed to initialize logger")?;
+    }
+    git::set_shallow(config.shallow_clone && !cli.full_clone);
 
-    cli.generate.run(&config)
+    match cli.command {
+        Some(Command::Init(init)) => init.run(),
+        Some(Command::Info(info)) => info.run(&config),
+        Some(Command::Get(get)) => get.run(&config),
+        Some(Command::List(list)) => list.run(&config),
+        Some(Command::Remove(remove)) => remove.run(&config),
+        Some(Command::Pin(pin)) => pin.run(&config),
+        Some(Command::Update(update)) => update.run(&config),
+        Some(Command::Cache(cache)) => cache.run(&config),
+        Some(Command::Check(check)) => check.run(&config),
+        Some(Command::Stats(stats)) => stats.run(&config),
+        Some(Command::Test(test)) => test.run(&config),
+        Some(Command::Fuzz(fuzz)) => fuzz.run(&config),
+        Some(Command::Pack(pack)) => pack.run(),
+        Some(Command::Replay(replay)) => replay.run(&config),
+        Some(Command::Upgrade(upgrade)) => upgrade.run(&config),
+        Some(Command::Undo(undo)) => undo.run(&config),
+        Some(Command::SelfUpdate(update)) => update.run(),
+        None => cli
+            .generate
+            .expect("generate arguments should be present without subcommand")
+            .run(&config),
+    }
 }
@@ -0,0 +1,122 @@
+use std::ops::Range;
+
+use similar::{capture_diff_slices, Algorithm, DiffOp, DiffTag};
+
+/// Ranges of `base` replaced by ranges of `side`.
+fn changes(base: &[&str], side: &[&str]) -> Vec<(Range<usize>, Range<usize>)> {
+    capture_diff_slices(Algorithm::Myers, base, side)
+        .iter()
+        .map(DiffOp::as_tag_tuple)
+        .filter(|(tag, _, _)| *tag != DiffTag::Equal)
+        .map(|(_, old, new)| (old, new))
+        .collect()
+}
+
+/// Lines of `base[lo..hi]` with the changes of a side applied.
+fn apply<'a>(
+    base: &[&'a str],
+    side: &[&'a str],
+    changes: &[(Range<usize>, Range<usize>)],
+    range: Range<usize>,
+) -> Vec<&'a str> {
+    let mut lines = Vec::new();
+    let mut pos = range.start;
+    for (old, new) in changes {
+        lines.extend(&base[pos..old.start]);
+        lines.extend(&side[new.clone()]);
+        pos = old.end;
+    }
+    lines.extend(&base[pos..range.end]);
+    lines
+}
+
+fn push_lines(merged: &mut String, lines: &[&str]) {
+    for line in lines {
+        merged.push_str(line);
+    }
+    // markers must start on lines of their own
+    if !merged.is_empty() && !merged.ends_with('\n') {
+        merged.push('\n');
+    }
+}
+
+fn push_conflict(merged: &mut String, ours: &[&str], theirs: &[&str], labels: (&str, &str)) {
+    push_lines(merged, &[]);
+    merged.push_str(&format!("<<<<<<< {}\n", labels.0));
+    push_lines(merged, ours);
+    merged.push_str("=======\n");
+    push_lines(merged, theirs);
+    merged.push_str(&format!(">>>>>>> {}\n", labels.1));
+}
+
+/// Merges two versions without a common base, marking every hunk in which they differ as a conflict.
+pub(crate) fn merge2(ours: &str, theirs: &str, labels: (&str, &str)) -> (String, usize) {
+    let ours = ours.split_inclusive('\n').collect::<Vec<_>>();
+    let theirs = theirs.split_inclusive('\n').collect::<Vec<_>>();
+    let mut merged = String::new();
+    let mut conflicts = 0;
+    for op in capture_diff_slices(Algorithm::Myers, &ours, &theirs) {
+        match op.as_tag_tuple() {
+            (DiffTag::Equal, old, _) => merged.extend(ours[old].iter().copied()),
+            (_, old, new) => {
+                conflicts += 1;
+                push_conflict(&mut merged, &ours[old], &theirs[new], labels);
+            }
+        }
+    }
+    (merged, conflicts)
+}
+
+/// Three-way merges the changes of `ours` and `theirs` made to `base` line by line,
+/// returning the merged text and the number of conflicts, which are marked as in git.
+pub(crate) fn merge3(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    labels: (&str, &str),
+) -> (String, usize) {
+    let base = base.split_inclusive('\n').collect::<Vec<_>>();
+    let ours = ours.split_inclusive('\n').collect::<Vec<_>>();
+    let theirs = theirs.split_inclusive('\n').collect::<Vec<_>>();
+    let (a, b) = (changes(&base, &ours), changes(&base, &theirs));
+    let (mut i, mut j) = (0, 0);
+    let mut pos = 0;
+    let mut merged = String::new();
+    let mut conflicts = 0;
+    while i < a.len() || j < b.len() {
+        // changes overlapping (or adjacent to) each other are merged as a whole
+        let (mut lo, mut hi) = match (a.get(i), b.get(j)) {
+            (Some((old, _)), Some((other, _))) if old.start <= other.start => (old.start, old.end),
+            (_, Some((old, _))) | (Some((old, _)), None) => (old.start, old.end),
+            (None, None) => unreachable!(),
+        };
+        let (si, sj) = (i, j);
+        loop {
+            if let Some((old, _)) = a.get(i).filter(|(old, _)| old.start <= hi && old.end >= lo) {
+                (lo, hi) = (lo.min(old.start), hi.max(old.end));
+                i += 1;
+            } else if let Some((old, _)) =
+                b.get(j).filter(|(old, _)| old.start <= hi && old.end >= lo)
+            {
+                (lo, hi) = (lo.min(old.start), hi.max(old.end));
+                j += 1;
+            } else {
+                break;
+            }
+        }
+        merged.extend(base[pos..lo].iter().copied());
+        let merged_ours = apply(&base, &ours, &a[si..i], lo..hi);
+        let merged_theirs = apply(&base, &theirs, &b[sj..j], lo..hi);
+        if sj == j || merged_ours == merged_theirs {
+            merged.extend(merged_ours);
+        } else if si == i {
+            merged.extend(merged_theirs);
+        } else {
+            conflicts += 1;
+            push_conflict(&mut merged, &merged_ours, &merged_theirs, labels);
+        }
+        pos = hi;
+    }
+    merged.extend(base[pos..].iter().copied());
+    (merged, conflicts)
+}
@@ -2,11 +2,14 @@ use std::path::{Path, PathBuf};
 
 use std::sync::OnceLock;
 
-use glob::{Pattern, PatternError};
+use glob::{MatchOptions, Pattern, PatternError};
+use indexmap::IndexMap;
+use minijinja::Syntax;
 use regex::Regex;
 use serde::Deserialize;
 
 use crate::utils::Result;
+use crate::variable::{Condition, Pattern as RegexPattern};
 
 #[derive(Debug, Deserialize)]
 #[serde(try_from = "String")]
@@ -49,7 +52,15 @@ impl TryFrom<Vec<String>> for GlobPatterns {
 
 impl GlobPatterns {
     pub fn matches_path_any<P: AsRef<Path>>(&self, path: P) -> bool {
-        self.0.iter().any(|p| p.matches_path(path.as_ref()))
+        // dotfiles are matched by wildcards like any other file, regardless of platform
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+        self.0
+            .iter()
+            .any(|p| p.matches_path_with(path.as_ref(), options))
     }
 
     pub(crate) fn push(&mut self, value: Pattern) {
@@ -57,6 +68,330 @@ impl GlobPatterns {
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Dotfiles {
+    #[default]
+    Keep,
+    RenameFrom(String),
+}
+
+impl TryFrom<String> for Dotfiles {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        static PATTERN: OnceLock<Regex> = OnceLock::new();
+        let pattern =
+            PATTERN.get_or_init(|| Regex::new(r"^rename-from\s+'(?<prefix>[^']+)'$").unwrap());
+        if value == "keep" {
+            return Ok(Self::Keep);
+        }
+        if let Some(captures) = pattern.captures(&value) {
+            let prefix = captures.name("prefix").unwrap().as_str();
+            return Ok(Self::RenameFrom(prefix.to_string()));
+        }
+        Err(format!(
+            "invalid dotfiles convention: '{value}' (expected \"keep\" or \"rename-from '<prefix>'\")"
+        ))
+    }
+}
+
+impl Dotfiles {
+    pub fn apply(&self, path: impl AsRef<Path>) -> PathBuf {
+        let Self::RenameFrom(prefix) = self else {
+            return path.as_ref().to_path_buf();
+        };
+        path.as_ref()
+            .components()
+            .map(|component| {
+                let name = component.as_os_str().to_string_lossy();
+                match name.strip_prefix(prefix.as_str()) {
+                    Some(rest) if !rest.is_empty() => format!(".{rest}"),
+                    _ => name.to_string(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Locales {
+    pub variable: String,
+    pub available: Vec<String>,
+}
+
+impl Locales {
+    pub fn strip<'a>(&'a self, filename: &str) -> Option<(String, &'a str)> {
+        let segments = filename.split('.').collect::<Vec<_>>();
+        segments
+            .iter()
+            .enumerate()
+            .skip(1)
+            .find_map(|(i, segment)| {
+                let locale = self.available.iter().find(|locale| locale == segment)?;
+                let mut segments = segments.clone();
+                segments.remove(i);
+                Some((segments.join("."), locale.as_str()))
+            })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    FirstWins,
+    #[default]
+    LastWins,
+    Error,
+}
+
+impl std::fmt::Display for Policy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FirstWins => write!(f, "first-wins"),
+            Self::LastWins => write!(f, "last-wins"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawConflicts {
+    Policy(Policy),
+    Table {
+        #[serde(default)]
+        default: Policy,
+        #[serde(flatten)]
+        paths: IndexMap<String, Policy>,
+    },
+}
+
+/// How to resolve an output path emitted by more than one composed template.
+#[derive(Debug, Default, Deserialize)]
+#[serde(try_from = "RawConflicts")]
+pub struct Conflicts {
+    pub default: Policy,
+    pub paths: Vec<(Pattern, Policy)>,
+}
+
+impl TryFrom<RawConflicts> for Conflicts {
+    type Error = PatternError;
+
+    fn try_from(conflicts: RawConflicts) -> Result<Self, Self::Error> {
+        match conflicts {
+            RawConflicts::Policy(default) => Ok(Self {
+                default,
+                paths: Vec::new(),
+            }),
+            RawConflicts::Table { default, paths } => Ok(Self {
+                default,
+                paths: paths
+                    .into_iter()
+                    .map(|(pattern, policy)| Ok((Pattern::new(&pattern)?, policy)))
+                    .collect::<Result<_, PatternError>>()?,
+            }),
+        }
+    }
+}
+
+impl Conflicts {
+    pub fn policy<P: AsRef<Path>>(&self, path: P) -> Policy {
+        self.paths
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(path.as_ref()))
+            .map(|(_, policy)| *policy)
+            .unwrap_or(self.default)
+    }
+}
+
+/// Permission modes of output paths, e.g. `"scripts/*" = "755"`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(try_from = "IndexMap<String, String>")]
+pub struct Modes(Vec<(Pattern, u32)>);
+
+impl TryFrom<IndexMap<String, String>> for Modes {
+    type Error = String;
+
+    fn try_from(modes: IndexMap<String, String>) -> Result<Self, Self::Error> {
+        modes
+            .into_iter()
+            .map(|(pattern, mode)| {
+                let pattern = Pattern::new(&pattern)
+                    .map_err(|err| format!("invalid mode pattern: '{pattern}' ({err})"))?;
+                Ok((pattern, parse_mode(&mode)?))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Modes {
+    pub fn mode<P: AsRef<Path>>(&self, path: P) -> Option<u32> {
+        self.0
+            .iter()
+            .find(|(pattern, _)| pattern.matches_path(path.as_ref()))
+            .map(|(_, mode)| *mode)
+    }
+
+    /// Appends the modes of `other`, which only apply to paths not matched so far.
+    pub(crate) fn extend(&mut self, other: Modes) {
+        self.0.extend(other.0)
+    }
+}
+
+/// Conditions of paths relative to the base, e.g. `"ci/**" = "use_ci"`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(try_from = "IndexMap<String, String>")]
+pub struct OnlyIf(Vec<(Pattern, Condition)>);
+
+impl TryFrom<IndexMap<String, String>> for OnlyIf {
+    type Error = String;
+
+    fn try_from(conditions: IndexMap<String, String>) -> Result<Self, Self::Error> {
+        conditions
+            .into_iter()
+            .map(|(pattern, condition)| {
+                let pattern = Pattern::new(&pattern)
+                    .map_err(|err| format!("invalid pattern: '{pattern}' ({err})"))?;
+                let condition = Condition::try_from(condition)
+                    .map_err(|err| format!("invalid condition of '{pattern}' ({err})"))?;
+                Ok((pattern, condition))
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl OnlyIf {
+    /// Conditions of the patterns matching the path or any of its ancestors.
+    pub fn conditions<'a>(
+        &'a self,
+        path: &'a Path,
+        is_dir: bool,
+    ) -> impl Iterator<Item = &'a Condition> + 'a {
+        let options = MatchOptions {
+            case_sensitive: true,
+            require_literal_separator: false,
+            require_literal_leading_dot: false,
+        };
+        self.0
+            .iter()
+            .filter(move |(pattern, _)| {
+                // a directory is matched with a trailing separator as well, as by "ci/**"
+                path.ancestors()
+                    .any(|path| pattern.matches_path_with(path, options))
+                    || (is_dir && pattern.matches_with(&format!("{}/", path.display()), options))
+            })
+            .map(|(_, condition)| condition)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Pattern, &Condition)> {
+        self.0
+            .iter()
+            .map(|(pattern, condition)| (pattern, condition))
+    }
+}
+
+/// Parses an octal permission mode like `"644"` or `"0755"`.
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "invalid mode: '{mode}' (expected octal like \"644\")"
+        )),
+    }
+}
+
+/// Jinja test defined by a template, e.g. `value is valid_crate_name`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum CustomTest {
+    Pattern(RegexPattern),
+    Expression { expression: String }, // with the tested value as `value`
+}
+
+/// Jinja function defined by a template, e.g. `{{ crate_name(name) }}`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum CustomFunction {
+    Command {
+        command: String, // with the arguments as positional parameters
+    },
+    Expression {
+        expression: String,
+        #[serde(default)]
+        args: Vec<String>, // names of the arguments in the expression
+    },
+}
+
+/// Section of variables in the prompt flow, skipped entirely unless its condition holds.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Group {
+    pub title: String,
+    pub description: Option<String>,
+    pub condition: Option<Condition>,
+}
+
+/// Pairs of start and end delimiters of the template syntax, each defaulting to that of Jinja.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Delimiters {
+    pub block: Option<(String, String)>,
+    pub variable: Option<(String, String)>,
+    pub comment: Option<(String, String)>,
+}
+
+impl Delimiters {
+    pub fn to_syntax(&self) -> Syntax {
+        let mut syntax = Syntax::default();
+        if let Some((start, end)) = &self.block {
+            syntax.block_start = start.clone().into();
+            syntax.block_end = end.clone().into();
+        }
+        if let Some((start, end)) = &self.variable {
+            syntax.variable_start = start.clone().into();
+            syntax.variable_end = end.clone().into();
+        }
+        if let Some((start, end)) = &self.comment {
+            syntax.comment_start = start.clone().into();
+            syntax.comment_end = end.clone().into();
+        }
+        syntax
+    }
+}
+
+/// Template whose output must have been applied to the destination, detected by its marker file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequiredTemplate {
+    pub source: String,
+    pub marker: PathBuf, // relative to the destination
+}
+
+/// File rendered once per item of an array variable, with `{item}` in its path replaced by the item.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Foreach {
+    pub path: PathBuf, // relative to the base
+    pub over: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TestCase {
+    #[serde(default)]
+    pub answers: toml::Table,
+    #[serde(default)]
+    pub exists: Vec<PathBuf>,
+    #[serde(default)]
+    pub absent: Vec<PathBuf>,
+    #[serde(default)]
+    pub contains: IndexMap<PathBuf, String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Metadata {
     #[serde(rename = "__name__")]
@@ -67,10 +402,103 @@ pub struct Metadata {
     pub url: Option<Url>,
     #[serde(rename = "__description__")]
     pub description: Option<String>,
+    #[serde(rename = "__version__")]
+    pub version: Option<String>,
     #[serde(rename = "__base__", default)]
     pub base: PathBuf, // relative path
+    #[serde(rename = "__extends__")]
+    pub extends: Option<String>, // source of the parent template
+    #[serde(rename = "__requires_templates__", default)]
+    pub requires_templates: Vec<RequiredTemplate>,
+    #[serde(rename = "__includes__")]
+    pub includes: Option<PathBuf>, // relative path
+    #[serde(rename = "__output_name__")]
+    pub output_name: Option<String>,
     #[serde(rename = "__copy__", default)]
     pub copy: GlobPatterns,
+    #[serde(rename = "__templates_suffix__")]
+    pub templates_suffix: Option<String>, // of the only files rendered, others being copied
     #[serde(rename = "__exclude__", default)]
     pub exclude: GlobPatterns,
+    #[serde(rename = "__dereference_symlinks__", default)]
+    pub dereference_symlinks: bool, // copying what symlinks point to instead of re-creating them
+    #[serde(rename = "__output_exclude__", default)]
+    pub output_exclude: Vec<String>, // patterns rendered with the answers
+    #[serde(rename = "__export_ignore__", default)]
+    pub export_ignore: bool,
+    #[serde(rename = "__verify__", default)]
+    pub verify: IndexMap<String, String>,
+    #[serde(rename = "__locales__")]
+    pub locales: Option<Locales>,
+    #[serde(rename = "__tests__", default)]
+    pub tests: IndexMap<String, TestCase>,
+    #[serde(rename = "__custom_tests__", default)]
+    pub custom_tests: IndexMap<String, CustomTest>,
+    #[serde(rename = "__functions__", default)]
+    pub functions: IndexMap<String, CustomFunction>,
+    #[serde(rename = "__dotfiles__", default)]
+    pub dotfiles: Dotfiles,
+    #[serde(rename = "__conflicts__", default)]
+    pub conflicts: Conflicts,
+    #[serde(rename = "__permissions__", alias = "__modes__", default)]
+    pub permissions: Modes, // of output paths relative to the output directory, set when generating and applying
+    #[serde(rename = "__delimiters__")]
+    pub delimiters: Option<Delimiters>,
+    #[serde(rename = "__foreach__", default)]
+    pub foreach: Vec<Foreach>,
+    #[serde(rename = "__only_if__", default)]
+    pub only_if: OnlyIf,
+    #[serde(rename = "__groups__", default)]
+    pub groups: IndexMap<String, Group>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> GlobPatterns {
+        GlobPatterns::try_from(patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn wildcards_match_dotfiles() {
+        assert!(patterns(&["*"]).matches_path_any(".gitignore"));
+        assert!(patterns(&["**/*.yml"]).matches_path_any(".github/workflows/ci.yml"));
+        assert!(patterns(&["src/*"]).matches_path_any("src/.env"));
+        assert!(!patterns(&["*.toml"]).matches_path_any(".gitignore"));
+    }
+
+    #[test]
+    fn parses_dotfiles_conventions() {
+        assert!(matches!(
+            Dotfiles::try_from("keep".to_string()),
+            Ok(Dotfiles::Keep)
+        ));
+        assert!(matches!(
+            Dotfiles::try_from("rename-from 'dot_'".to_string()),
+            Ok(Dotfiles::RenameFrom(prefix)) if prefix == "dot_"
+        ));
+        assert!(Dotfiles::try_from("rename-from dot_".to_string()).is_err());
+        assert!(Dotfiles::try_from("hide".to_string()).is_err());
+    }
+
+    #[test]
+    fn keeps_paths_by_default() {
+        assert_eq!(
+            Dotfiles::Keep.apply("dot_config/dot_env"),
+            Path::new("dot_config/dot_env")
+        );
+    }
+
+    #[test]
+    fn renames_every_prefixed_component() {
+        let dotfiles = Dotfiles::RenameFrom("dot_".to_string());
+        assert_eq!(
+            dotfiles.apply("dot_github/workflows/dot_ci.yml"),
+            Path::new(".github/workflows/.ci.yml")
+        );
+        assert_eq!(dotfiles.apply("src/main.rs"), Path::new("src/main.rs"));
+        // a component of the bare prefix would become nameless
+        assert_eq!(dotfiles.apply("dot_/file"), Path::new("dot_/file"));
+    }
 }
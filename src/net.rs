@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Context as _, Result};
+use minijinja::{Environment, Error, ErrorKind};
+
+const USER_AGENT: &str = concat!("tapgen/", env!("CARGO_PKG_VERSION"));
+
+pub(crate) fn fetch(url: &str) -> Result<Vec<u8>> {
+    log::info!("fetching '{url}'");
+    let command = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location"])
+        .args(["--user-agent", USER_AGENT])
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .context("failed to execute curl command")?;
+    if !command.status.success() {
+        let stderr = String::from_utf8_lossy(&command.stderr);
+        bail!("failed to fetch '{url}': {}", stderr.trim())
+    }
+    Ok(command.stdout)
+}
+
+pub(crate) fn fetch_json(url: &str) -> Result<serde_json::Value> {
+    let buf = fetch(url)?;
+    serde_json::from_slice(&buf).context(format!("failed to parse response of '{url}'"))
+}
+
+fn latest_version(registry: &str, name: &str) -> Result<String> {
+    let (url, pointer) = match registry {
+        "crates.io" => (
+            format!("https://crates.io/api/v1/crates/{name}"),
+            "/crate/max_stable_version",
+        ),
+        "npm" => (
+            format!("https://registry.npmjs.org/{name}/latest"),
+            "/version",
+        ),
+        "pypi" => (
+            format!("https://pypi.org/pypi/{name}/json"),
+            "/info/version",
+        ),
+        _ => unreachable!(),
+    };
+    let json = fetch_json(&url)?;
+    match json.pointer(pointer).and_then(|version| version.as_str()) {
+        Some(version) => Ok(version.to_string()),
+        None => bail!("unexpected response of '{url}'"),
+    }
+}
+
+pub(crate) fn register(environment: &mut Environment<'static>, allow_net: bool) {
+    let cache = Arc::new(Mutex::new(HashMap::<(String, String), String>::new()));
+    for (function, registry) in [
+        ("crate_version", "crates.io"),
+        ("npm_version", "npm"),
+        ("pypi_version", "pypi"),
+    ] {
+        let cache = cache.clone();
+        environment.add_function(function, move |name: String| {
+            if !allow_net {
+                return Err(Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("{function}() requires network access (enable with --allow-net)"),
+                ));
+            }
+            let key = (registry.to_string(), name.clone());
+            if let Some(version) = cache.lock().unwrap().get(&key) {
+                return Ok(version.clone());
+            }
+            let version = latest_version(registry, &name).map_err(|err| {
+                Error::new(
+                    ErrorKind::InvalidOperation,
+                    format!("cannot query latest version of '{name}' on {registry}: {err:#}"),
+                )
+            })?;
+            cache.lock().unwrap().insert(key, version.clone());
+            Ok(version)
+        });
+    }
+}
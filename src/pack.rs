@@ -0,0 +1,135 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result};
+use clap::Args;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use tapgen::template::{self, Template};
+
+#[derive(Serialize)]
+struct Manifest {
+    name: String,
+    author: String,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize)]
+struct ManifestFile {
+    path: String,
+    sha256: String,
+}
+
+#[derive(Clone, Args)]
+pub(crate) struct Pack {
+    #[arg(help = "Directory of template to be packed.")]
+    src: PathBuf,
+    #[arg(
+        short = 'o',
+        long = "output",
+        value_name = "PATH",
+        help = "Path of the archive to be written."
+    )]
+    output: Option<PathBuf>,
+}
+
+pub(crate) fn sha256_hex(buf: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(buf))
+}
+
+fn slugify(s: &str) -> String {
+    s.to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn template_files(template: &Template) -> Vec<PathBuf> {
+    let mut files = vec![PathBuf::from(template.path.file_name().unwrap())];
+    for hook in ["tapgen.before.hook", "tapgen.after.hook"] {
+        if template.root.join(hook).exists() {
+            files.push(PathBuf::from(hook));
+        }
+    }
+    for entry in template.entries.values().flatten() {
+        if entry.file_type().is_file() {
+            files.push(
+                entry
+                    .path()
+                    .strip_prefix(&template.root)
+                    .unwrap()
+                    .to_path_buf(),
+            );
+        }
+    }
+    files
+}
+
+impl Pack {
+    pub(crate) fn run(&self) -> Result<()> {
+        let mut path = self.src.clone();
+        if path.is_dir() {
+            path = template::definition_path(&path);
+        }
+        let template = Template::load(&path)
+            .context(format!("failed to load template from '{}'", path.display()))?;
+        let output = self
+            .output
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(format!("{}.tgz", slugify(&template.metadata.name))));
+
+        let file = File::create(&output)
+            .context(format!("failed to create archive: '{}'", output.display()))?;
+        let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+        let mut manifest = Manifest {
+            name: template.metadata.name.clone(),
+            author: template.metadata.author.clone(),
+            files: Vec::new(),
+        };
+        for relative in template_files(&template) {
+            let absolute = template.root.join(&relative);
+            let buf = fs::read(&absolute)
+                .context(format!("failed to read file: '{}'", absolute.display()))?;
+            manifest.files.push(ManifestFile {
+                path: relative.to_string_lossy().replace('\\', "/"),
+                sha256: sha256_hex(&buf),
+            });
+            builder
+                .append_path_with_name(&absolute, &relative)
+                .context(format!("failed to pack file: '{}'", relative.display()))?;
+        }
+        let contents = toml::to_string_pretty(&manifest)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "tapgen.manifest.toml", contents.as_bytes())
+            .context("failed to pack manifest")?;
+        builder
+            .into_inner()
+            .and_then(|encoder| encoder.finish())
+            .context("failed to finish archive")?;
+
+        let checksum = sha256_hex(&fs::read(&output)?);
+        let filename = output.file_name().unwrap().to_string_lossy();
+        let checksum_path = PathBuf::from(format!("{}.sha256", output.display()));
+        fs::write(&checksum_path, format!("{checksum}  {filename}\n")).context(format!(
+            "failed to write checksum: '{}'",
+            checksum_path.display()
+        ))?;
+
+        println!(
+            "Packed {} files of '{}'.",
+            manifest.files.len(),
+            template.metadata.name
+        );
+        println!("=> '{}'", output.display());
+        println!("sha256: {checksum}");
+        Ok(())
+    }
+}
@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{Map, Value};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Prints human-readable output, unless in porcelain mode.
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::porcelain::enabled() {
+            println!($($arg)*);
+        }
+    };
+}
+
+pub(crate) fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Emits an event as a line of json, if in porcelain mode.
+pub(crate) fn emit(event: &str, fields: Value) {
+    if !enabled() {
+        return;
+    }
+    let mut object = Map::new();
+    object.insert(String::from("event"), Value::from(event));
+    if let Value::Object(fields) = fields {
+        object.extend(fields);
+    }
+    println!("{}", Value::Object(object));
+}
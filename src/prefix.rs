@@ -1,9 +1,11 @@
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::OnceLock;
 
-use anyhow::{bail, Error, Result};
+use anyhow::{bail, Context as _, Error, Result};
 use regex::Regex;
+use serde::Deserialize;
 
 #[derive(Clone)]
 pub(crate) struct Source(PathBuf);
@@ -34,3 +36,47 @@ impl AsRef<Path> for Source {
         &self.0
     }
 }
+
+impl Source {
+    pub(crate) fn name(&self) -> String {
+        self.0
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Index {
+    #[serde(rename = "template", default)]
+    pub(crate) entries: Vec<Entry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Entry {
+    pub(crate) name: String,
+    pub(crate) description: Option<String>,
+    pub(crate) source: String,
+}
+
+impl Index {
+    pub(crate) fn load(prefix: impl AsRef<Path>) -> Result<Self> {
+        let path = prefix.as_ref().join("index.toml");
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .context(format!("failed to read prefix index: '{}'", path.display()))?;
+        toml::from_str(&contents).context(format!(
+            "failed to parse prefix index: '{}'",
+            path.display()
+        ))
+    }
+
+    pub(crate) fn find(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+}
@@ -1,10 +1,20 @@
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::OnceLock;
 
 use dialoguer::theme::SimpleTheme;
-use dialoguer::{Confirm, Input, InputValidator, MultiSelect, Select};
+use dialoguer::{Confirm, Editor, Input, InputValidator, MultiSelect, Password, Select};
 
 static THEME: OnceLock<SimpleTheme> = OnceLock::new();
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+pub(crate) fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+pub(crate) fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
 
 pub(crate) fn select<P: Into<String>, T: ToString + Clone>(
     prompt: P,
@@ -29,32 +39,49 @@ pub(crate) fn multi_select<P: Into<String>, T: ToString + Clone>(
     items: &[T],
     defaults: Option<&[T]>,
 ) -> Vec<T> {
+    const SHORTCUTS: [&str; 2] = ["<select all>", "<select none>"];
+
     let theme = THEME.get_or_init(|| SimpleTheme);
-    let mut p = MultiSelect::with_theme(theme)
+    let defaults = defaults
+        .unwrap_or_default()
+        .iter()
+        .map(|default| default.to_string())
+        .collect::<Vec<String>>();
+    // the defaults are shown checked, so they are not listed again
+    let labels = SHORTCUTS
+        .iter()
+        .map(|shortcut| shortcut.to_string())
+        .chain(items.iter().map(|item| item.to_string()))
+        .collect::<Vec<String>>();
+    let mut checked = vec![false; SHORTCUTS.len()];
+    checked.extend(
+        items
+            .iter()
+            .map(|item| defaults.contains(&item.to_string())),
+    );
+    let selected = MultiSelect::with_theme(theme)
         .with_prompt(prompt)
-        .items(items);
-    if let Some(defaults) = defaults {
-        if !defaults.is_empty() {
-            let defaults = defaults
-                .iter()
-                .map(|default| default.to_string())
-                .collect::<Vec<String>>();
-            p = p.defaults(
-                &items
-                    .iter()
-                    .map(|choice| defaults.contains(&choice.to_string()))
-                    .collect::<Vec<bool>>(),
-            )
-        }
+        .items(&labels)
+        .defaults(&checked)
+        .interact()
+        .unwrap();
+    if selected.contains(&0) {
+        return items.to_vec();
     }
-    p.interact()
-        .unwrap()
+    if selected.contains(&1) {
+        return Vec::new();
+    }
+    selected
         .iter()
-        .map(|&i| items[i].clone())
+        .map(|&i| items[i - SHORTCUTS.len()].clone())
         .collect()
 }
 
 pub(crate) fn confirm(prompt: impl Into<String>, default: Option<bool>) -> bool {
+    // safety prompts default to no, so they are not answered yes without being asked
+    if ASSUME_YES.load(Ordering::Relaxed) {
+        return default.unwrap_or(false);
+    }
     let theme = THEME.get_or_init(|| SimpleTheme);
     let mut p = Confirm::with_theme(theme).with_prompt(prompt);
     if let Some(default) = default {
@@ -63,6 +90,15 @@ pub(crate) fn confirm(prompt: impl Into<String>, default: Option<bool>) -> bool
     p.interact().unwrap()
 }
 
+pub(crate) fn boolean(prompt: impl Into<String>, default: bool) -> bool {
+    let theme = THEME.get_or_init(|| SimpleTheme);
+    Confirm::with_theme(theme)
+        .with_prompt(prompt)
+        .default(default)
+        .interact()
+        .unwrap()
+}
+
 pub(crate) fn input<'a, T: 'a, V>(
     prompt: impl Into<String>,
     default: Option<T>,
@@ -84,3 +120,32 @@ where
     }
     p.interact_text().unwrap()
 }
+
+/// Prompts for input without echoing it; an empty input is only accepted with `allow_empty`.
+pub(crate) fn password<'a, V>(
+    prompt: impl Into<String>,
+    allow_empty: bool,
+    validator: Option<V>,
+) -> String
+where
+    V: InputValidator<String> + 'a,
+    V::Err: ToString,
+{
+    let theme = THEME.get_or_init(|| SimpleTheme);
+    let mut p = Password::with_theme(theme)
+        .with_prompt(prompt)
+        .allow_empty_password(allow_empty);
+    if let Some(validator) = validator {
+        p = p.validate_with(validator);
+    }
+    p.interact().unwrap()
+}
+
+/// Opens `$VISUAL` or `$EDITOR` with `default`, returning it unchanged if the editor is closed without saving.
+pub(crate) fn editor(prompt: impl AsRef<str>, default: &str) -> String {
+    say!("{} (opening editor...)", prompt.as_ref());
+    Editor::new()
+        .edit(default)
+        .unwrap()
+        .unwrap_or_else(|| default.to_string())
+}
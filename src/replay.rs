@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{bail, Result};
+use clap::Args;
+
+use crate::answers::Answers;
+use crate::config::Config;
+use crate::generate::Generate;
+use crate::source::Source;
+
+#[derive(Clone, Args)]
+pub(crate) struct Replay {
+    #[arg(help = "Answers file saved by a previous generation (with --save-answers).")]
+    answers: PathBuf,
+    #[arg(help = "Destination of generated output to be applied to.")]
+    dst: Option<PathBuf>,
+    #[arg(short = 'O', long = "overwrite", help = "Overwrite existing files.")]
+    overwrite: bool,
+}
+
+impl Replay {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let answers = Answers::load(&self.answers)?;
+        let Some(source) = answers.header_str("__source__") else {
+            bail!(
+                "answers file does not record its template source: '{}'",
+                self.answers.display()
+            )
+        };
+        let src = Source::from_str(source)?;
+        println!("Replaying generation from '{src}'...");
+        Generate::replay(src, self.dst.clone(), self.answers.clone(), self.overwrite).run(config)
+    }
+}
@@ -0,0 +1,71 @@
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context as _, Result};
+use tapgen::variable::{Variable, VariableValue};
+
+use crate::config::Config;
+use crate::shell;
+
+fn output(command: &mut Command, what: &str) -> Result<String> {
+    let command = command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .output()
+        .context(format!("failed to execute {what}"))?;
+    if !command.status.success() {
+        bail!("{what} failed with {}", command.status)
+    }
+    let output = String::from_utf8(command.stdout).context("secret encoding should be utf-8")?;
+    // only the first line, like `pass` stores passwords
+    Ok(output.lines().next().unwrap_or_default().to_string())
+}
+
+/// Fetches the secret from the backend of `source`:
+/// `pass:<entry>`, `op://<vault>/<item>/<field>` or `command:<command line>`.
+pub(crate) fn fetch(name: &str, source: &str) -> Result<String> {
+    log::info!("fetching secret variable '{name}'");
+    if let Some(entry) = source.strip_prefix("pass:") {
+        output(Command::new("pass").arg("show").arg(entry), "pass")
+    } else if source.starts_with("op://") {
+        output(Command::new("op").arg("read").arg(source), "1password cli")
+    } else if let Some(line) = source.strip_prefix("command:") {
+        output(
+            shell::command(line).env("TAPGEN_SECRET_NAME", name),
+            "secret command",
+        )
+    } else {
+        bail!("unrecognized secret source (expected 'pass:', 'op://' or 'command:')")
+    }
+}
+
+/// Resolves a secret variable from its own source, or from the secret command
+/// (`TAPGEN_SECRET_COMMAND` or `secret_command` in config) if any.
+pub(crate) fn resolve(config: &Config, name: &str, variable: &Variable) -> Result<Option<String>> {
+    let source = match &variable.secret_source {
+        Some(source) => source.clone(),
+        None => match std::env::var("TAPGEN_SECRET_COMMAND")
+            .ok()
+            .or_else(|| config.secret_command.clone())
+        {
+            Some(line) => format!("command:{line}"),
+            None => return Ok(None),
+        },
+    };
+    let secret =
+        fetch(name, &source).context(format!("failed to fetch secret variable: '{name}'"))?;
+    if let VariableValue::String {
+        pattern: Some(pattern),
+        ..
+    } = &variable.value
+    {
+        // the secret itself is never echoed
+        if !pattern.is_match(&secret) {
+            bail!(
+                "secret variable '{name}' does not match pattern: `{}`",
+                pattern.as_str()
+            )
+        }
+    }
+    Ok(Some(secret))
+}
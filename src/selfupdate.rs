@@ -0,0 +1,128 @@
+use std::env::consts::{ARCH, OS};
+use std::fs;
+
+use anyhow::{bail, Context as _, Result};
+use clap::Args;
+
+use crate::net;
+use crate::pack::sha256_hex;
+use crate::prompt;
+
+const RELEASES_URL: &str = "https://api.github.com/repos/tnychn/tapgen/releases/latest";
+const CHECKSUMS_ASSET: &str = "SHA256SUMS";
+
+#[derive(Clone, Args)]
+pub(crate) struct SelfUpdate {
+    #[arg(
+        long = "check",
+        help = "Only check whether a newer version is available."
+    )]
+    check: bool,
+    #[arg(
+        short = 'y',
+        long = "yes",
+        help = "Replace the executable without confirmation."
+    )]
+    yes: bool,
+}
+
+/// Numeric components of a version like `v1.2.3`, ignoring pre-release suffixes.
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['-', '+'])
+        .next()
+        .unwrap_or_default()
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+fn asset_name() -> String {
+    if OS == "windows" {
+        format!("tapgen-{ARCH}-{OS}.exe")
+    } else {
+        format!("tapgen-{ARCH}-{OS}")
+    }
+}
+
+fn asset_url(release: &serde_json::Value, name: &str) -> Result<String> {
+    release
+        .get("assets")
+        .and_then(|assets| assets.as_array())
+        .into_iter()
+        .flatten()
+        .find(|asset| asset.get("name").and_then(|n| n.as_str()) == Some(name))
+        .and_then(|asset| asset.get("browser_download_url"))
+        .and_then(|url| url.as_str())
+        .map(String::from)
+        .context(format!("no release asset named '{name}'"))
+}
+
+/// Expected checksum of `name` in a `sha256sum`-style listing.
+fn expected_checksum(checksums: &str, name: &str) -> Option<String> {
+    checksums.lines().find_map(|line| {
+        let (checksum, file) = line.split_once(char::is_whitespace)?;
+        (file.trim().trim_start_matches('*') == name).then(|| checksum.to_lowercase())
+    })
+}
+
+impl SelfUpdate {
+    pub(crate) fn run(&self) -> Result<()> {
+        let current = env!("CARGO_PKG_VERSION");
+        let release = net::fetch_json(RELEASES_URL).context("failed to query latest release")?;
+        let Some(tag) = release.get("tag_name").and_then(|tag| tag.as_str()) else {
+            bail!("unexpected response of '{RELEASES_URL}'")
+        };
+        let latest = tag.trim_start_matches('v');
+        println!("Current version: {current}");
+        println!("Latest version: {latest}");
+        if parse_version(latest) <= parse_version(current) {
+            println!("Already up to date.");
+            return Ok(());
+        }
+        if self.check {
+            println!("A newer version is available (run `tapgen self-update`).");
+            return Ok(());
+        }
+
+        let name = asset_name();
+        let checksums = net::fetch(&asset_url(&release, CHECKSUMS_ASSET)?)?;
+        let Some(expected) = expected_checksum(&String::from_utf8_lossy(&checksums), &name) else {
+            bail!("no checksum of '{name}' in {CHECKSUMS_ASSET}")
+        };
+        println!("Downloading '{name}'...");
+        let binary = net::fetch(&asset_url(&release, &name)?)?;
+        let actual = sha256_hex(&binary);
+        if actual != expected {
+            bail!("checksum mismatch of '{name}': expected {expected}, got {actual}")
+        }
+        log::info!("verified checksum of '{name}': {actual}");
+
+        let exe = std::env::current_exe().context("failed to locate current executable")?;
+        let exe = exe.canonicalize().unwrap_or(exe);
+        if !self.yes && !prompt::confirm(format!("Replace '{}'?", exe.display()), Some(true)) {
+            return Ok(());
+        }
+        // written next to the executable so that it can be renamed over it
+        let new = exe.with_extension("new");
+        fs::write(&new, &binary).context(format!("failed to write '{}'", new.display()))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            fs::set_permissions(&new, fs::Permissions::from_mode(0o755))?;
+        }
+        #[cfg(windows)]
+        {
+            // a running executable cannot be replaced, but can be renamed
+            let old = exe.with_extension("old");
+            let _ = fs::remove_file(&old);
+            fs::rename(&exe, &old).context("failed to move current executable")?;
+        }
+        fs::rename(&new, &exe)
+            .context(format!("failed to replace executable: '{}'", exe.display()))?;
+        log::info!("updated '{}' from {current} to {latest}", exe.display());
+        println!("Updated tapgen from {current} to {latest}!");
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use minijinja::value::Rest;
+use minijinja::{Environment, Error, ErrorKind, Value};
+use tapgen::metadata::CustomFunction;
+use tapgen::variable::parse_choices;
+use tapgen::Template;
+
+pub(crate) fn command(line: &str) -> Command {
+    #[cfg(windows)]
+    {
+        let mut command = Command::new("cmd");
+        command.arg("/C").arg(line);
+        command
+    }
+    #[cfg(not(windows))]
+    {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(line);
+        command
+    }
+}
+
+pub(crate) fn quote(path: impl AsRef<std::path::Path>) -> String {
+    let s = path.as_ref().to_string_lossy();
+    #[cfg(windows)]
+    {
+        format!("\"{}\"", s.replace('"', "\\\""))
+    }
+    #[cfg(not(windows))]
+    {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+fn exec_error(what: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidOperation,
+        format!("{what} requires command execution (enable with --allow-exec)"),
+    )
+}
+
+/// Runs the command line in `cwd` with the arguments as positional parameters,
+/// returning its output without the trailing newline.
+fn exec(line: &str, args: &[Value], cwd: &Path) -> Result<String, Error> {
+    log::info!("executing '{line}' for template");
+    let mut command = command(line);
+    // the first argument after the command line becomes `$0`
+    #[cfg(not(windows))]
+    command.arg("tapgen");
+    let output = command
+        .args(args.iter().map(Value::to_string))
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidOperation,
+                format!("cannot execute command: '{line}'"),
+            )
+            .with_source(err)
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::new(
+            ErrorKind::InvalidOperation,
+            format!(
+                "command '{line}' failed with {}: {}",
+                output.status,
+                stderr.trim()
+            ),
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Registers the `shell()` function, the `exec` filter and the command functions of the template,
+/// which run commands in the template root, failing unless `allow_exec`.
+pub(crate) fn register(template: &mut Template, allow_exec: bool) {
+    let environment: &mut Environment<'static> = &mut template.environment;
+    {
+        let root = template.root.clone();
+        environment.add_function("shell", move |line: String| {
+            if !allow_exec {
+                return Err(exec_error("shell()"));
+            }
+            exec(&line, &[], &root)
+        });
+    }
+    {
+        let root = template.root.clone();
+        environment.add_filter("exec", move |line: String| {
+            if !allow_exec {
+                return Err(exec_error("exec filter"));
+            }
+            exec(&line, &[], &root)
+        });
+    }
+    for (name, function) in &template.metadata.functions {
+        let CustomFunction::Command { command } = function else {
+            continue;
+        };
+        let (root, what, command) = (
+            template.root.clone(),
+            format!("function '{name}'"),
+            command.clone(),
+        );
+        environment.add_function(
+            name.clone(),
+            move |args: Rest<Value>| -> Result<String, Error> {
+                if !allow_exec {
+                    return Err(exec_error(&what));
+                }
+                exec(&command, &args, &root)
+            },
+        );
+    }
+}
+
+/// Loads the choices of variables produced by `choices_cmd`, which requires `allow_exec`.
+pub(crate) fn load_choices(template: &mut Template, allow_exec: bool) -> anyhow::Result<()> {
+    for (name, variable) in &mut template.variables {
+        let Some(line) = &variable.choices_cmd else {
+            continue;
+        };
+        if !allow_exec {
+            return Err(exec_error(&format!("choices_cmd of variable '{name}'")).into());
+        }
+        let choices = parse_choices(&exec(line, &[], &template.root)?);
+        if choices.is_empty() {
+            anyhow::bail!("command produced no choices for variable: '{name}'");
+        }
+        variable.set_choices(choices);
+    }
+    Ok(())
+}
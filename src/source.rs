@@ -0,0 +1,227 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use anyhow::{bail, Context as _, Error, Result};
+use clap::Args;
+use indexmap::IndexMap;
+use regex::Regex;
+use tapgen::template::{self, Template};
+
+use crate::archive::{self, Source as ArchiveSource};
+use crate::cache;
+use crate::config::Config;
+use crate::git::Source as GitSource;
+use crate::prefix::{Index, Source as PrefixSource};
+use crate::{net, shell};
+
+#[derive(Clone)]
+pub(crate) enum Source {
+    Path(PathBuf),
+    Git(GitSource),
+    Prefix(PrefixSource),
+    Archive(ArchiveSource),
+}
+
+static ALIASES: OnceLock<IndexMap<String, String>> = OnceLock::new();
+
+/// Sets the aliases of sources from config; must be called before parsing any source.
+pub(crate) fn set_aliases(aliases: IndexMap<String, String>) {
+    let _ = ALIASES.set(aliases);
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // aliases are expanded once, so that they cannot refer to each other
+        match ALIASES.get().and_then(|aliases| aliases.get(s)) {
+            Some(target) => {
+                log::debug!("expanded alias '{s}' to '{target}'");
+                Self::parse(target)
+                    .context(format!("failed to parse source of alias '{s}': '{target}'"))
+            }
+            None => Self::parse(s),
+        }
+    }
+}
+
+impl Source {
+    fn parse(s: &str) -> Result<Self> {
+        static SCHEME: OnceLock<Regex> = OnceLock::new();
+        let scheme =
+            SCHEME.get_or_init(|| Regex::new(r"^(?<scheme>[a-zA-Z][a-zA-Z0-9+.-]+):").unwrap());
+
+        if let Some(path) = s.strip_prefix("file://") {
+            return Ok(Self::Path(PathBuf::from(path)));
+        }
+        if s.starts_with("https://") || s.starts_with("http://") {
+            return Ok(Self::Archive(ArchiveSource::from_str(s)?));
+        }
+        if let Ok(source) = GitSource::from_str(s) {
+            return Ok(Self::Git(source));
+        } else if let Ok(source) = PrefixSource::from_str(s) {
+            return Ok(Self::Prefix(source));
+        }
+        let path = PathBuf::from(s);
+        if path.exists() {
+            return Ok(Self::Path(path));
+        }
+        if let Some(captures) = scheme.captures(s) {
+            let scheme = captures.name("scheme").unwrap().as_str();
+            match scheme {
+                "github" | "gitlab" | "bitbucket" => {
+                    bail!("malformed git source: '{s}' (expected '{scheme}:<owner>/<repo>[@<ref>][/<path>]' or '{scheme}:<owner>/<repo>[@<ref>]//[<path>]')")
+                }
+                "ssh" => bail!(
+                    "malformed ssh source: '{s}' (expected 'ssh://[<user>@]<host>[:<port>]/<owner>/<repo>[.git][@<ref>][//<path>]')"
+                ),
+                _ => bail!(
+                    "unrecognized source scheme: '{scheme}' (expected one of github, gitlab, bitbucket, ssh, file)"
+                ),
+            }
+        }
+        bail!("no such file or directory: '{s}'")
+    }
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Git(source) => write!(f, "{}", source.shorthand()),
+            Self::Prefix(source) => write!(f, "@:{}", source.name()),
+            Self::Archive(source) => write!(f, "{source}"),
+        }
+    }
+}
+
+impl Source {
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            Self::Path(_) => "path",
+            Self::Git(source) => {
+                if source.path.is_some() {
+                    "git+path"
+                } else {
+                    "git"
+                }
+            }
+            Self::Prefix(_) => "prefix",
+            Self::Archive(_) => "archive",
+        }
+    }
+
+    /// Clones or downloads what the source needs without prompting or progress output,
+    /// so that several sources can be fetched concurrently before being resolved.
+    pub(crate) fn prefetch(&self, prefix: impl AsRef<Path>) -> Result<bool> {
+        match self {
+            Self::Git(source) => source.prefetch(prefix),
+            Self::Archive(source) => source.prefetch(prefix),
+            _ => Ok(false),
+        }
+    }
+
+    pub(crate) fn resolve(&self, prefix: impl AsRef<Path>) -> Result<PathBuf> {
+        self.resolve_indexed(prefix.as_ref(), &mut Vec::new())
+    }
+
+    /// Resolves the source, `names` being those of the index entries resolved on the way here.
+    fn resolve_indexed(&self, prefix: &Path, names: &mut Vec<String>) -> Result<PathBuf> {
+        let mut path = match self {
+            Self::Git(source) => source
+                .resolve(prefix)
+                .context(format!("failed to resolve git source: '{source}'"))?,
+            Self::Prefix(source) => {
+                if names.contains(&source.name()) {
+                    bail!("index alias cycle: '@:{}'", source.name())
+                }
+                names.push(source.name());
+                let index = Index::load(prefix)?;
+                match index.find(&source.name()) {
+                    Some(entry) if prefix.join(&entry.source).exists() => {
+                        prefix.join(&entry.source)
+                    }
+                    Some(entry) => match Self::from_str(&entry.source)? {
+                        Self::Prefix(inner) if inner.name() == source.name() => prefix.join(source),
+                        inner => {
+                            return inner.resolve_indexed(prefix, names).context(format!(
+                                "failed to resolve indexed source: '{}'",
+                                entry.source
+                            ))
+                        }
+                    },
+                    None => prefix.join(source),
+                }
+            }
+            Self::Archive(source) => source
+                .resolve(prefix)
+                .context(format!("failed to resolve archive source: '{source}'"))?,
+            Self::Path(path) => archive::resolve_local(prefix, path)
+                .context(format!("failed to extract archive: '{}'", path.display()))?
+                .unwrap_or_else(|| path.clone()),
+        };
+        if path.is_dir() {
+            path = template::definition_path(&path);
+        }
+        let path = path.canonicalize().context(format!(
+            "failed to resolve path: '{}' (source kind: {})",
+            path.display(),
+            self.kind()
+        ))?;
+        // cookiecutter templates are not catalogued
+        if matches!(self, Self::Git(_) | Self::Prefix(_) | Self::Archive(_))
+            && path.ends_with("tapgen.toml")
+        {
+            // archives are extracted under names that do not tell their sources
+            let source = matches!(self, Self::Archive(_)).then(|| self.to_string());
+            if let Err(err) = cache::update_catalog(prefix, &path, source) {
+                log::warn!("cannot update catalog: {err:#}");
+            }
+        }
+        Ok(path)
+    }
+}
+
+/// Loads the template at `path`, resolving the sources it extends like any other source.
+pub(crate) fn load_template(path: &Path, prefix: &Path) -> Result<Template> {
+    Template::load_with(path, &|source| {
+        Ok(Source::from_str(source)?.resolve(prefix)?)
+    })
+    .context(format!("failed to load template from '{}'", path.display()))
+}
+
+/// Opt-ins of templates to access the network and execute commands while rendering.
+#[derive(Clone, Copy, Default, Args)]
+pub(crate) struct Allow {
+    #[arg(
+        long = "allow-net",
+        help = "Allow template functions to access the network."
+    )]
+    pub(crate) net: bool,
+    #[arg(
+        long = "allow-exec",
+        help = "Allow templates to execute commands while rendering."
+    )]
+    pub(crate) exec: bool,
+}
+
+/// Loads the template at `path` to be rendered, registering the functions that access the network
+/// or execute commands on it and the templates it extends, which fail unless allowed by `allow`
+/// or the config.
+pub(crate) fn load_gated_template(path: &Path, config: &Config, allow: Allow) -> Result<Template> {
+    let mut template = load_template(path, &config.prefix)?;
+    let (allow_net, allow_exec) = (
+        allow.net || config.allow_net,
+        allow.exec || config.allow_exec,
+    );
+    // parents render their own files with their own environments
+    let mut current = Some(&mut template);
+    while let Some(template) = current {
+        net::register(&mut template.environment, allow_net);
+        shell::register(template, allow_exec);
+        current = template.parent.as_deref_mut();
+    }
+    shell::load_choices(&mut template, allow_exec)?;
+    Ok(template)
+}
@@ -0,0 +1,100 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::Result;
+use clap::Args;
+use walkdir::WalkDir;
+
+use tapgen::variable::VariableValue;
+
+use crate::config::Config;
+use crate::source::{self, Source};
+
+const LARGEST: usize = 5;
+
+#[derive(Clone, Args)]
+pub(crate) struct Stats {
+    #[arg(
+        help = "Source of template to be analyzed.",
+        value_parser = Source::from_str,
+    )]
+    src: Source,
+}
+
+impl Stats {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let path = self.src.resolve(&config.prefix)?;
+        let template = source::load_template(&path, &config.prefix)?;
+
+        let (mut templated, mut copied, mut excluded) = (0, 0, 0);
+        let mut total = 0;
+        let mut sizes = Vec::new();
+        for entry in WalkDir::new(&template.base)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().is_file())
+        {
+            let name = entry.path().strip_prefix(&template.root).unwrap();
+            if template.is_excluded(name, false) {
+                excluded += 1;
+                continue;
+            }
+            if template.metadata.copy.matches_path_any(name) {
+                copied += 1;
+            } else {
+                templated += 1;
+            }
+            let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            total += size;
+            sizes.push((size, name.to_path_buf()));
+        }
+        sizes.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let mut kinds = BTreeMap::new();
+        for variable in template.variables.values() {
+            let kind = match &variable.value {
+                VariableValue::String { .. } => "string",
+                VariableValue::Array { .. } => "array",
+                VariableValue::Integer { .. } => "integer",
+                VariableValue::Boolean { .. } => "boolean",
+                VariableValue::Path { .. } => "path",
+                VariableValue::Date { .. } => "date",
+                VariableValue::Map { .. } => "map",
+            };
+            *kinds.entry(kind).or_insert(0) += 1;
+        }
+        let conditions = template
+            .variables
+            .values()
+            .filter(|variable| variable.condition.is_some())
+            .count()
+            + template.conditions.len();
+        let hooks = ["before", "after"]
+            .into_iter()
+            .filter(|stage| template.root.join(format!("tapgen.{stage}.hook")).exists())
+            .collect::<Vec<_>>();
+
+        println!("[Files]");
+        println!("│ templated: {templated}");
+        println!("│ copied: {copied}");
+        println!("│ excluded: {excluded}");
+        println!("│ total size: {total} bytes");
+        println!("[Variables]");
+        for (kind, count) in &kinds {
+            println!("│ {kind}: {count}");
+        }
+        println!("│ conditions: {conditions}");
+        println!("[Hooks]");
+        if hooks.is_empty() {
+            println!("│ (none)");
+        }
+        for stage in hooks {
+            println!("│ {stage}");
+        }
+        println!("[Largest]");
+        for (size, name) in sizes.iter().take(LARGEST) {
+            println!("│ {} ({size} bytes)", name.display());
+        }
+        Ok(())
+    }
+}
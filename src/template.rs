@@ -1,17 +1,21 @@
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 use glob::Pattern;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use indexmap::IndexMap;
-use minijinja::{Environment, Value};
+use minijinja::{context, Environment, State, Value};
+use regex::Regex;
 use tempfile::TempDir;
 use toml::Table;
 use walkdir::{DirEntry, WalkDir};
 
-use crate::metadata::Metadata;
-use crate::utils::{self, Error, Result};
-use crate::variable::Variable;
+use crate::metadata::{CustomFunction, CustomTest, Delimiters, Foreach, GlobPatterns, Metadata};
+use crate::utils::{self, BoxError, Error, InvalidVariableError, Result};
+use crate::variable::{parse_choices, Choice, Condition, Variable, VariableValue};
+use crate::{attributes, cookiecutter, copier, functions};
 
 pub struct Template {
     pub path: PathBuf,
@@ -22,43 +26,272 @@ pub struct Template {
     pub variables: IndexMap<String, Variable>,
 
     pub entries: BTreeMap<usize, Vec<DirEntry>>,
+    pub conditions: HashMap<String, Condition>,
     pub environment: Environment<'static>,
+
+    pub ignore: Gitignore,             // of `.tapgenignore`
+    pub parent: Option<Box<Template>>, // of `__extends__`
+    pub cookiecutter: bool,            // converted from a `cookiecutter.json`
+}
+
+/// Pattern of the front matter comment between the comment delimiters, e.g. `{# tapgen: if docker #}`.
+fn front_matter_pattern(delimiters: Option<&Delimiters>) -> Regex {
+    let (start, end) = delimiters
+        .and_then(|delimiters| delimiters.comment.as_ref())
+        .map_or(("{#", "#}"), |(start, end)| (start.as_str(), end.as_str()));
+    Regex::new(&format!(
+        r"^{}-?\s*tapgen:\s*if\s+(?<condition>.+?)\s*-?{}",
+        regex::escape(start),
+        regex::escape(end)
+    ))
+    .unwrap()
+}
+
+fn front_matter_condition<'a>(pattern: &Regex, source: &'a str) -> Option<&'a str> {
+    let line = source.lines().next()?;
+    pattern
+        .captures(line)
+        .map(|captures| captures.name("condition").unwrap().as_str())
+}
+
+/// Whether a rendered path has a segment rendered to nothing, e.g. `{% if docker %}docker{% endif %}/`,
+/// which omits the entry along with its children.
+fn has_empty_segment(rendered: &str) -> bool {
+    // backslashes are escaped by `render_path`, being separators on windows
+    rendered
+        .replace("\\\\", "\\")
+        .split(std::path::is_separator)
+        .any(|segment| segment.trim().is_empty())
 }
 
+/// Whether the path or any of its ancestors, relative to the output directory, is excluded.
+fn is_output_excluded(excludes: &GlobPatterns, path: &Path) -> bool {
+    let mut components = path.components();
+    components.next(); // the output directory itself
+    components
+        .as_path()
+        .ancestors()
+        .filter(|path| !path.as_os_str().is_empty())
+        .any(|path| excludes.matches_path_any(path))
+}
+
+/// Whether the target of a symlink at `link` (relative to a root directory) is relative
+/// and resolves inside that root without leaving it on the way.
+pub fn is_link_contained(link: impl AsRef<Path>, target: impl AsRef<Path>) -> bool {
+    // the depth of the directory containing the link
+    let mut depth = link.as_ref().components().count() as isize - 1;
+    for component in target.as_ref().components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+/// Creates a symlink at `link` pointing to `target`, which is a directory if `is_dir`,
+/// failing if the target leaves the root directory that `name` (of the link) is relative to.
+pub fn symlink(name: &Path, target: &Path, link: &Path, is_dir: bool) -> Result<()> {
+    if !is_link_contained(name, target) {
+        return Err(Error::InvalidSymlink(
+            utils::path_to_string(name),
+            utils::path_to_string(target),
+        ));
+    }
+    Ok(utils::symlink(target, link, is_dir)?)
+}
+
+/// File of gitignore patterns at the template root, excluded in addition to `__exclude__`.
+pub const IGNORE_FILE: &str = ".tapgenignore";
+
+/// Path of the definition file in the template directory `dir`, being its `cookiecutter.json`
+/// or `copier.yml` (in that order) in the absence of a `tapgen.toml`.
+pub fn definition_path(dir: impl AsRef<Path>) -> PathBuf {
+    let dir = dir.as_ref();
+    ["tapgen.toml", cookiecutter::CONTEXT_FILE]
+        .into_iter()
+        .chain(copier::CONFIG_FILES)
+        .map(|name| dir.join(name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join("tapgen.toml"))
+}
+
+/// Resolves a source of `__extends__` to the path of a template.
+pub type Resolve = dyn Fn(&str) -> std::result::Result<PathBuf, BoxError>;
+
 impl Template {
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
-        let path = fs::canonicalize(&path)?;
+        Self::load_with(path, &|source| {
+            Err(format!("no such file or directory: '{source}'").into())
+        })
+    }
+
+    /// Like `load`, but resolves sources of `__extends__` other than relative paths with `resolve`.
+    pub fn load_with(path: impl AsRef<Path>, resolve: &Resolve) -> Result<Self> {
+        Self::load_chain(path.as_ref(), resolve, &mut Vec::new())
+    }
+
+    fn load_chain(path: &Path, resolve: &Resolve, chain: &mut Vec<PathBuf>) -> Result<Self> {
+        let path = fs::canonicalize(path)?;
+        if chain.contains(&path) {
+            return Err(Error::CyclicExtends(path));
+        }
+        chain.push(path.clone());
+        let root = path.parent().unwrap().to_path_buf();
         let contents = fs::read_to_string(&path)?;
+        let file_name = utils::path_to_string(path.file_name().unwrap());
+        let is_cookiecutter = file_name == cookiecutter::CONTEXT_FILE;
+        let contents = if is_cookiecutter {
+            cookiecutter::to_definition(&root, &contents)?.to_string()
+        } else if copier::CONFIG_FILES.contains(&file_name.as_str()) {
+            copier::to_definition(&root, &contents)?.to_string()
+        } else {
+            contents
+        };
 
-        let metadata = toml::from_str::<Metadata>(&contents)?;
+        let mut metadata = toml::from_str::<Metadata>(&contents)?;
 
-        let table = contents.parse::<Table>()?;
+        let mut parent = match &metadata.extends {
+            Some(source) => {
+                // paths are relative to the extending template
+                let local = root.join(source);
+                let path = if local.exists() {
+                    local
+                } else {
+                    resolve(source).map_err(|err| Error::ResolveParent(source.clone(), err))?
+                };
+                let path = if path.is_dir() {
+                    definition_path(path)
+                } else {
+                    path
+                };
+                Some(Box::new(Self::load_chain(&path, resolve, chain)?))
+            }
+            None => None,
+        };
+        // variables and groups of the parent come first, unless overridden
         let mut variables = IndexMap::new();
+        if let Some(parent) = &mut parent {
+            variables = std::mem::take(&mut parent.variables);
+            let mut groups = std::mem::take(&mut parent.metadata.groups);
+            groups.extend(std::mem::take(&mut metadata.groups));
+            metadata.groups = groups;
+            let mut verify = std::mem::take(&mut parent.metadata.verify);
+            verify.extend(std::mem::take(&mut metadata.verify));
+            metadata.verify = verify;
+            // the output includes the files of the parent, so do its permissions
+            let permissions = std::mem::take(&mut parent.metadata.permissions);
+            metadata.permissions.extend(permissions);
+        }
+
+        let table = contents.parse::<Table>()?;
         for (name, value) in table {
             if !(name.starts_with("__") && name.ends_with("__")) {
                 let variable = value.try_into::<Variable>()?;
-                let variable = variable.validate().map_err(|err| Error::ValidateVariable {
-                    name: name.clone(),
-                    source: err,
-                })?;
+                let mut variable = variable
+                    .validate()
+                    .and_then(|variable| match &variable.group {
+                        Some(group) if !metadata.groups.contains_key(group) => {
+                            Err(InvalidVariableError::UndefinedGroup(group.clone()))
+                        }
+                        _ => Ok(variable),
+                    })
+                    .map_err(|err| Error::ValidateVariable {
+                        name: name.clone(),
+                        source: err,
+                    })?;
+                if let Some(file) = &variable.choices_file {
+                    let contents = fs::read_to_string(functions::scoped_path(&root, file)?)?;
+                    variable.set_choices(parse_choices(&contents));
+                }
                 variables.insert(name, variable);
             }
         }
 
-        let root = path.parent().unwrap().to_path_buf();
+        if metadata.export_ignore {
+            for pattern in attributes::export_ignore_patterns(&root) {
+                metadata.exclude.push(pattern);
+            }
+        }
+        let mut builder = GitignoreBuilder::new(&root);
+        if root.join(IGNORE_FILE).is_file() {
+            if let Some(err) = builder.add(root.join(IGNORE_FILE)) {
+                return Err(Error::Ignore(err));
+            }
+        }
+        let ignore = builder.build().map_err(Error::Ignore)?;
         let base = root
             .join(&metadata.base)
             .canonicalize()
             .map_err(Error::CanonicalizeBasePath)?;
+        for rule in &metadata.foreach {
+            let is_array = matches!(
+                variables.get(&rule.over),
+                Some(Variable {
+                    value: VariableValue::Array { .. },
+                    ..
+                })
+            );
+            if !is_array {
+                return Err(Error::InvalidForeach(format!(
+                    "'{}' is not an array variable",
+                    rule.over
+                )));
+            }
+            if !base.join(&rule.path).is_file() {
+                return Err(Error::InvalidForeach(format!(
+                    "no such file: '{}'",
+                    rule.path.display()
+                )));
+            }
+        }
 
         let entries = BTreeMap::new();
+        let conditions = HashMap::new();
         let mut environment = Environment::new();
+        if let Some(delimiters) = &metadata.delimiters {
+            environment.set_syntax(delimiters.to_syntax())?;
+        }
         environment.add_filter("slugify", |s: String| {
             s.to_lowercase()
                 .split_whitespace()
                 .collect::<Vec<_>>()
                 .join("-")
         });
+        functions::register(&mut environment, &root);
+        let path_functions = root.join("tapgen.functions.toml");
+        if path_functions.exists() {
+            let contents = fs::read_to_string(&path_functions)?;
+            let functions = toml::from_str::<IndexMap<String, CustomFunction>>(&contents)?;
+            for (name, function) in functions {
+                // functions defined in `tapgen.toml` take precedence
+                metadata.functions.entry(name).or_insert(function);
+            }
+        }
+        functions::register_custom(&mut environment, &root, &metadata.functions)?;
+        for (name, test) in &metadata.custom_tests {
+            match test {
+                CustomTest::Pattern(pattern) => {
+                    let pattern = pattern.clone();
+                    environment
+                        .add_test(name.clone(), move |value: String| pattern.is_match(&value));
+                }
+                CustomTest::Expression { expression } => {
+                    // compiled eagerly to report syntax errors on load
+                    environment.compile_expression(expression)?;
+                    let expression = expression.clone();
+                    environment.add_test(name.clone(), move |state: &State, value: Value| {
+                        let result = state
+                            .env()
+                            .compile_expression(&expression)?
+                            .eval(context! { value })?;
+                        Ok::<_, minijinja::Error>(result.is_true())
+                    });
+                }
+            }
+        }
 
         Self {
             path,
@@ -67,26 +300,82 @@ impl Template {
             metadata,
             variables,
             entries,
+            conditions,
             environment,
+            ignore,
+            parent,
+            cookiecutter: is_cookiecutter,
         }
         .init()
     }
 
+    /// Whether the path relative to the root is excluded by `__exclude__` or `.tapgenignore`.
+    pub fn is_excluded(&self, path: impl AsRef<Path>, is_dir: bool) -> bool {
+        let path = path.as_ref();
+        path == Path::new(IGNORE_FILE)
+            || self.metadata.exclude.matches_path_any(path)
+            || self
+                .ignore
+                .matched_path_or_any_parents(path, is_dir)
+                .is_ignore()
+    }
+
     fn init(mut self) -> Result<Self> {
-        let walker = WalkDir::new(&self.base).sort_by_file_name();
+        let includes = self
+            .metadata
+            .includes
+            .as_ref()
+            .map(|path| self.root.join(path));
+        if let Some(includes) = &includes {
+            for entry in WalkDir::new(includes).sort_by_file_name() {
+                let entry = entry.map_err(|err| err.into_io_error().unwrap())?;
+                if entry.file_type().is_file() {
+                    let name = entry.path().strip_prefix(includes).unwrap();
+                    let source = fs::read_to_string(entry.path())?;
+                    self.environment
+                        .add_template_owned(utils::path_to_string(name), source)?;
+                }
+            }
+        }
+        let front_matter = front_matter_pattern(self.metadata.delimiters.as_ref());
+        let walker = WalkDir::new(&self.base)
+            .follow_links(self.metadata.dereference_symlinks)
+            .sort_by_file_name();
         for entry in walker {
             let entry = entry.map_err(|err| err.into_io_error().unwrap())?;
             let path = entry.path().strip_prefix(&self.root).unwrap();
-            if self.metadata.exclude.matches_path_any(path) {
+            // the base directory is the output itself, so it cannot be ignored
+            if entry.path() != self.base && self.is_excluded(path, entry.file_type().is_dir()) {
+                continue;
+            }
+            // includes are only rendered as part of other files
+            if includes
+                .as_ref()
+                .is_some_and(|includes| entry.path().starts_with(includes))
+            {
                 continue;
             }
             if entry.file_type().is_file() {
                 let buf = fs::read(entry.path())?;
                 let name = utils::path_to_string(path);
-                if utils::is_binary_buf(&buf) {
-                    self.metadata.copy.push(Pattern::new(&name).unwrap())
+                let is_template = match &self.metadata.templates_suffix {
+                    Some(suffix) => name.ends_with(suffix.as_str()),
+                    None => true,
+                };
+                if utils::is_binary_buf(&buf) || !is_template {
+                    self.metadata
+                        .copy
+                        .push(Pattern::new(&Pattern::escape(&name)).unwrap())
                 } else if !self.metadata.copy.matches_path_any(path) {
                     let source = String::from_utf8(buf).expect("file encoding should be utf-8");
+                    let source = match self.cookiecutter {
+                        true => cookiecutter::translate(&source).into_owned(),
+                        false => source,
+                    };
+                    if let Some(condition) = front_matter_condition(&front_matter, &source) {
+                        let condition = Condition::try_from(condition.to_string())?;
+                        self.conditions.insert(name.clone(), condition);
+                    }
                     self.environment.add_template_owned(name, source)?;
                 }
             }
@@ -102,6 +391,10 @@ impl Template {
         values: &HashMap<String, Value>,
     ) -> Result<String, minijinja::Error> {
         let source = utils::path_to_string(path);
+        let source = match self.cookiecutter {
+            true => cookiecutter::translate(&source),
+            false => Cow::Borrowed(source.as_str()),
+        };
         let source = source.escape_default().collect::<String>();
         self.environment.render_str(&source, values)
     }
@@ -112,43 +405,296 @@ impl Template {
         dst: impl AsRef<Path>,
         values: &HashMap<String, Value>,
     ) -> Result<()> {
+        // the permissions of the source are kept, as `fs::copy` does for copied files
+        let permissions = fs::metadata(self.root.join(&name))?.permissions();
         let name = utils::path_to_string(name);
         let template = self.environment.get_template(&name)?;
-        let file = File::create(dst)?;
+        let file = File::create(&dst)?;
         template.render_to_write(values, file)?;
+        fs::set_permissions(dst, permissions)?;
         Ok(())
     }
 
+    /// Output paths of a file rendered once per item of the rule, each with `item` in its values.
+    fn expand_foreach<'a>(
+        &self,
+        rule: &Foreach,
+        rendered_path: &Path,
+        values: &'a HashMap<String, Value>,
+    ) -> Result<Vec<(PathBuf, Cow<'a, HashMap<String, Value>>)>> {
+        // the variable may have been skipped by its condition
+        let Some(items) = values.get(&rule.over) else {
+            return Ok(Vec::new());
+        };
+        let rendered_path = utils::path_to_string(rendered_path);
+        let mut targets = Vec::new();
+        for item in items.try_iter()? {
+            let name = item.to_string();
+            if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+                return Err(Error::InvalidOutputName(name));
+            }
+            let mut values = values.clone();
+            values.insert(String::from("item"), item);
+            targets.push((
+                PathBuf::from(rendered_path.replace("{item}", &name)),
+                Cow::Owned(values),
+            ));
+        }
+        Ok(targets)
+    }
+
     pub fn generate(&self, values: &HashMap<String, Value>) -> Result<Output> {
-        let mut basename: Option<String> = None;
-        let tempdir = TempDir::with_prefix("tapgen-")?;
-        for entry in self.entries.values().flatten() {
+        self.generate_in(values, std::env::temp_dir())
+    }
+
+    pub fn generate_in(
+        &self,
+        values: &HashMap<String, Value>,
+        dir: impl AsRef<Path>,
+    ) -> Result<Output> {
+        self.generate_in_with(values, dir, |_| {})
+    }
+
+    /// Like `generate_in`, but calls `on_file` with the output name of every file before it is written.
+    pub fn generate_in_with(
+        &self,
+        values: &HashMap<String, Value>,
+        dir: impl AsRef<Path>,
+        mut on_file: impl FnMut(&str),
+    ) -> Result<Output> {
+        let mut rendered = Vec::new();
+        fs::create_dir_all(&dir)?;
+        let tempdir = TempDir::with_prefix_in("tapgen-", dir)?;
+        let output_name = match &self.metadata.output_name {
+            Some(output_name) => {
+                let rendered = self.environment.render_str(output_name, values)?;
+                let rendered = rendered.trim();
+                if rendered.is_empty()
+                    || rendered.contains(['/', '\\'])
+                    || rendered == "."
+                    || rendered == ".."
+                {
+                    return Err(Error::InvalidOutputName(rendered.to_string()));
+                }
+                Some(rendered.to_string())
+            }
+            None => None,
+        };
+        let excludes = self.output_excludes(values)?;
+        let basename = self
+            .render_entries(
+                values,
+                tempdir.path(),
+                output_name.as_deref(),
+                &excludes,
+                false,
+                &mut on_file,
+                &mut rendered,
+            )?
+            .ok_or(Error::ExcludedBase)?;
+        // files of the parents are only emitted where not overlaid by those of their children
+        let mut parent = self.parent.as_deref();
+        while let Some(template) = parent {
+            template.render_entries(
+                values,
+                tempdir.path(),
+                Some(&basename),
+                &excludes,
+                true,
+                &mut on_file,
+                &mut rendered,
+            )?;
+            parent = template.parent.as_deref();
+        }
+        self.apply_permissions(&tempdir.path().join(&basename))?;
+        Ok(Output {
+            tempdir,
+            basename,
+            rendered,
+        })
+    }
+
+    /// Patterns of `__output_exclude__` rendered with the answers, those rendered empty being dropped.
+    fn output_excludes(&self, values: &HashMap<String, Value>) -> Result<GlobPatterns> {
+        let mut patterns = Vec::new();
+        for pattern in &self.metadata.output_exclude {
+            let rendered = self.environment.render_str(pattern, values)?;
+            let rendered = rendered.trim();
+            if !rendered.is_empty() {
+                patterns.push(rendered.to_string());
+            }
+        }
+        GlobPatterns::try_from(patterns).map_err(|err| Error::InvalidOutputExclude(err.to_string()))
+    }
+
+    /// Sets the modes of `__permissions__` on the matching paths of the output at `base`.
+    fn apply_permissions(&self, base: &Path) -> Result<()> {
+        for entry in WalkDir::new(base).min_depth(1) {
+            let entry = entry.map_err(|err| err.into_io_error().unwrap())?;
+            let relative = entry.path().strip_prefix(base).unwrap();
+            let Some(mode) = self.metadata.permissions.mode(relative) else {
+                continue;
+            };
+            // the mode of a symlink is that of its target
+            if entry.path_is_symlink() {
+                continue;
+            }
+            log::debug!("chmod {mode:o} '{}'", relative.display());
+            #[cfg(unix)]
+            let permissions = {
+                use std::os::unix::fs::PermissionsExt as _;
+                fs::Permissions::from_mode(mode)
+            };
+            // only the write bits of the owner have an equivalent elsewhere
+            #[cfg(not(unix))]
+            let permissions = {
+                let mut permissions = fs::metadata(entry.path())?.permissions();
+                permissions.set_readonly(mode & 0o200 == 0);
+                permissions
+            };
+            fs::set_permissions(entry.path(), permissions)?;
+        }
+        Ok(())
+    }
+
+    /// Renders the entries into `dir`, returning the rendered name of the base directory.
+    fn render_entries(
+        &self,
+        values: &HashMap<String, Value>,
+        dir: &Path,
+        output_name: Option<&str>,
+        excludes: &GlobPatterns,
+        overlay: bool,
+        on_file: &mut dyn FnMut(&str),
+        rendered: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<Option<String>> {
+        let mut basename = None;
+        'entries: for entry in self.entries.values().flatten() {
             let raw_name = entry.path().strip_prefix(&self.root).unwrap();
-            let rendered_name = self.render_path(raw_name, values)?;
-            let rendered_path = tempdir.path().join(&rendered_name);
+            let relative = entry.path().strip_prefix(&self.base).unwrap();
+            if !relative.as_os_str().is_empty() {
+                let is_dir = entry.file_type().is_dir();
+                for condition in self.metadata.only_if.conditions(relative, is_dir) {
+                    if !condition.eval(&self.environment, values)?.is_true() {
+                        log::debug!("omit '{}'", raw_name.display());
+                        continue 'entries;
+                    }
+                }
+            }
+            let rendered_name = match output_name {
+                // the base directory is named by the output name instead of its own name
+                Some(output_name) if relative.as_os_str().is_empty() => output_name.to_string(),
+                Some(output_name) => {
+                    let rest = self.render_path(relative, values)?;
+                    if has_empty_segment(&rest) {
+                        log::debug!("omit '{}' (empty name)", raw_name.display());
+                        continue;
+                    }
+                    utils::path_to_string(Path::new(output_name).join(rest))
+                }
+                None => {
+                    let name = self.render_path(raw_name, values)?;
+                    if entry.path() != self.base && has_empty_segment(&name) {
+                        log::debug!("omit '{}' (empty name)", raw_name.display());
+                        continue;
+                    }
+                    name
+                }
+            };
+            let rendered_name = utils::path_to_string(self.metadata.dotfiles.apply(rendered_name));
+            let mut rendered_path = dir.join(&rendered_name);
             if entry.path() == self.base {
-                basename = Some(rendered_name);
+                basename = Some(rendered_name.clone());
             }
             if entry.file_type().is_file() {
-                if self.metadata.copy.matches_path_any(entry.path()) {
-                    fs::copy(entry.path(), rendered_path)?;
-                } else {
-                    self.render_template(raw_name, rendered_path, values)?;
+                if let Some(condition) = self.conditions.get(&utils::path_to_string(raw_name)) {
+                    if !condition.eval(&self.environment, values)?.is_true() {
+                        log::debug!("omit '{}'", raw_name.display());
+                        continue;
+                    }
+                }
+                if let Some(suffix) = &self.metadata.templates_suffix {
+                    let filename = utils::path_to_string(rendered_path.file_name().unwrap());
+                    if let Some(stripped) = filename.strip_suffix(suffix.as_str()) {
+                        if utils::path_to_string(raw_name).ends_with(suffix.as_str()) {
+                            rendered_path.set_file_name(stripped);
+                        }
+                    }
+                }
+                if let Some(locales) = &self.metadata.locales {
+                    let filename = utils::path_to_string(rendered_path.file_name().unwrap());
+                    if let Some((stripped, locale)) = locales.strip(&filename) {
+                        let selected = values.get(&locales.variable).map(Choice::key_of);
+                        if selected.as_ref().and_then(Value::as_str) != Some(locale) {
+                            continue;
+                        }
+                        rendered_path.set_file_name(stripped);
+                    }
                 }
+                let targets = match self
+                    .metadata
+                    .foreach
+                    .iter()
+                    .find(|rule| rule.path == relative)
+                {
+                    Some(rule) => self.expand_foreach(rule, &rendered_path, values)?,
+                    None => vec![(rendered_path, Cow::Borrowed(values))],
+                };
+                for (rendered_path, values) in targets {
+                    if is_output_excluded(excludes, rendered_path.strip_prefix(dir).unwrap()) {
+                        log::debug!("exclude '{}'", raw_name.display());
+                        continue;
+                    }
+                    if overlay && rendered_path.exists() {
+                        log::debug!("overlaid '{}'", raw_name.display());
+                        continue;
+                    }
+                    let rendered_name =
+                        utils::path_to_string(rendered_path.strip_prefix(dir).unwrap());
+                    on_file(&rendered_name);
+                    if self.metadata.copy.matches_path_any(raw_name) {
+                        log::debug!("copy '{}' -> '{}'", raw_name.display(), rendered_name);
+                        fs::copy(entry.path(), rendered_path)?;
+                    } else {
+                        log::debug!("render '{}' -> '{}'", raw_name.display(), rendered_name);
+                        self.render_template(raw_name, &rendered_path, &values)?;
+                        rendered.push((entry.path().to_path_buf(), rendered_path));
+                    }
+                }
+            } else if is_output_excluded(excludes, rendered_path.strip_prefix(dir).unwrap()) {
+                log::debug!("exclude '{}'", raw_name.display());
             } else if entry.file_type().is_dir() {
                 fs::create_dir_all(rendered_path)?;
+            } else if entry.file_type().is_symlink() {
+                if overlay && rendered_path.symlink_metadata().is_ok() {
+                    log::debug!("overlaid '{}'", raw_name.display());
+                    continue;
+                }
+                on_file(&utils::path_to_string(
+                    rendered_path.strip_prefix(dir).unwrap(),
+                ));
+                // the target is rendered like a path, so that it can follow renamed entries
+                let target = self.render_path(fs::read_link(entry.path())?, values)?;
+                // links out of the output would point into the template or the temporary directory
+                let mut relative = rendered_path.strip_prefix(dir).unwrap().components();
+                relative.next(); // the output directory itself
+                log::debug!("link '{}' -> '{}'", raw_name.display(), target);
+                symlink(
+                    relative.as_path(),
+                    Path::new(&target),
+                    &rendered_path,
+                    entry.path().is_dir(),
+                )?;
             }
         }
-        Ok(Output {
-            tempdir,
-            basename: basename.expect("basename should be determined"),
-        })
+        Ok(basename)
     }
 }
 
 pub struct Output {
     tempdir: TempDir,
     basename: String,
+    rendered: Vec<(PathBuf, PathBuf)>,
 }
 
 impl Output {
@@ -160,6 +706,15 @@ impl Output {
         self.path().join(&self.basename)
     }
 
+    pub fn basename(&self) -> &str {
+        &self.basename
+    }
+
+    /// Pairs of source and output paths of every rendered (i.e. not copied) file.
+    pub fn rendered(&self) -> &[(PathBuf, PathBuf)] {
+        &self.rendered
+    }
+
     pub fn into_tempdir(self) -> TempDir {
         self.tempdir
     }
@@ -0,0 +1,102 @@
+use std::fs;
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _, Result};
+use clap::Args;
+
+use tapgen::coerce::{self, Origin};
+use tapgen::metadata::TestCase;
+use tapgen::template::Template;
+
+use crate::config::Config;
+use crate::generate::{builtin_values, render_default, resolve_variables};
+use crate::source::{self, Allow, Source};
+
+#[derive(Clone, Args)]
+pub(crate) struct Test {
+    #[arg(
+        help = "Source of template to be tested.",
+        value_parser = Source::from_str,
+    )]
+    src: Source,
+    #[command(flatten)]
+    allow: Allow,
+}
+
+impl Test {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let path = self.src.resolve(&config.prefix)?;
+        let template = source::load_gated_template(&path, config, self.allow)?;
+        if template.metadata.tests.is_empty() {
+            println!("No tests defined in template.");
+            return Ok(());
+        }
+        let mut failures = 0;
+        for (name, case) in &template.metadata.tests {
+            let errors =
+                run_case(&template, config, case).unwrap_or_else(|err| vec![format!("{err:#}")]);
+            if errors.is_empty() {
+                println!("test {name} ... ok");
+            } else {
+                failures += 1;
+                println!("test {name} ... FAILED");
+                for error in errors {
+                    println!("│ {error}");
+                }
+            }
+        }
+        println!();
+        let total = template.metadata.tests.len();
+        println!("{} passed; {failures} failed", total - failures);
+        if failures > 0 {
+            bail!("{failures} of {total} template tests failed")
+        }
+        Ok(())
+    }
+}
+
+fn run_case(template: &Template, config: &Config, case: &TestCase) -> Result<Vec<String>> {
+    let mut values = builtin_values(config)?;
+    resolve_variables(template, &mut values, |name, variable, values| {
+        match case.answers.get(name) {
+            Some(answer) => Ok(coerce::from_toml(
+                &template.environment,
+                name,
+                variable,
+                answer,
+                Origin::AnswersFile,
+            )?),
+            None => Ok(match render_default(template, variable, values)? {
+                Some(default) => variable.string_value(&default),
+                None => variable.default_value(&template.environment),
+            }),
+        }
+    })?;
+    let output = template
+        .generate(&values)
+        .context("failed to generate from template")?;
+    let base = output.base();
+
+    let mut errors = Vec::new();
+    for path in &case.exists {
+        if !base.join(path).exists() {
+            errors.push(format!("expected to exist: '{}'", path.display()));
+        }
+    }
+    for path in &case.absent {
+        if base.join(path).exists() {
+            errors.push(format!("expected to be absent: '{}'", path.display()));
+        }
+    }
+    for (path, needle) in &case.contains {
+        match fs::read_to_string(base.join(path)) {
+            Ok(contents) if contents.contains(needle.as_str()) => {}
+            Ok(_) => errors.push(format!(
+                "expected '{}' to contain: {needle:?}",
+                path.display()
+            )),
+            Err(err) => errors.push(format!("cannot read '{}': {err}", path.display())),
+        }
+    }
+    Ok(errors)
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context as _, Result};
+use clap::Args;
+
+use crate::config::Config;
+use crate::journal::{self, Action, Journal};
+
+#[derive(Clone, Args)]
+pub(crate) struct Undo {
+    #[arg(default_value = ".", help = "Destination the output was applied to.")]
+    dst: PathBuf,
+    #[arg(
+        short = 'f',
+        long = "force",
+        help = "Also undo files that have been modified since they were applied."
+    )]
+    force: bool,
+}
+
+impl Undo {
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let journal = Journal::load(&config.prefix, &self.dst)?;
+        let (mut removed, mut restored, mut kept) = (0, 0, 0);
+        // in reverse, so that directories are emptied before they are removed
+        for entry in journal.entries.iter().rev() {
+            let path = self.dst.join(&entry.path);
+            if entry.action == Action::CreatedDir {
+                // directories with files of others are left in place
+                if fs::remove_dir(&path).is_ok() {
+                    log::debug!("remove directory '{}'", path.display());
+                }
+                continue;
+            }
+            let modified = match journal::checksum(&path) {
+                Ok(checksum) => Some(checksum) != entry.sha256,
+                Err(_) if entry.action == Action::Created => continue,
+                Err(_) => true,
+            };
+            if modified && !self.force {
+                println!("Kept modified file: '{}'", entry.path.display());
+                kept += 1;
+                continue;
+            }
+            match entry.action {
+                Action::Created => {
+                    log::debug!("remove '{}'", path.display());
+                    fs::remove_file(&path)
+                        .context(format!("failed to remove file: '{}'", path.display()))?;
+                    removed += 1;
+                }
+                Action::Overwritten => {
+                    log::debug!("restore '{}'", path.display());
+                    fs::copy(journal.original(&entry.path), &path)
+                        .context(format!("failed to restore file: '{}'", path.display()))?;
+                    restored += 1;
+                }
+                Action::CreatedDir => unreachable!(),
+            }
+        }
+        println!("Removed {removed} files. Restored {restored} files. Kept {kept} files.");
+        if kept > 0 {
+            println!("Run again with --force to undo the modified files as well.");
+        } else {
+            journal.clear()?;
+        }
+        Ok(())
+    }
+}
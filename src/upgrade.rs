@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{bail, Context as _, Result};
+use clap::Args;
+use minijinja::Value;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+use tapgen::coerce::{self, Origin};
+use tapgen::template::Template;
+
+use crate::answers::{Answers, ANSWERS_FILE};
+use crate::config::Config;
+use crate::generate::{
+    answers_table, builtin_values, render_default, resolve_variables, template_value,
+};
+use crate::source::{self, Allow, Source};
+use crate::{git, merge};
+
+#[derive(Clone, Args)]
+pub(crate) struct Upgrade {
+    #[arg(
+        default_value = ".",
+        help = "Project generated with its answers saved in it (with --save-answers)."
+    )]
+    project: PathBuf,
+    #[arg(
+        long = "answers",
+        value_name = "PATH",
+        help = "Answers file of the project, if not saved in it."
+    )]
+    answers: Option<PathBuf>,
+    #[arg(
+        long = "from",
+        value_name = "SRC",
+        help = "Source of the template the project was generated from, instead of the recorded commit.",
+        value_parser = Source::from_str,
+    )]
+    from: Option<Source>,
+    #[command(flatten)]
+    allow: Allow,
+    #[arg(
+        long = "dry-run",
+        help = "Show the changes to the project without applying them."
+    )]
+    dry_run: bool,
+}
+
+/// Values of the recorded answers, with variables not answered taking their defaults.
+fn resolve_answers(
+    config: &Config,
+    template: &Template,
+    src: &Source,
+    answers: &Answers,
+) -> Result<HashMap<String, Value>> {
+    let mut values = builtin_values(config)?;
+    values.insert(String::from("_template"), template_value(template, src)?);
+    resolve_variables(
+        template,
+        &mut values,
+        |name, variable, values| match answers.values.get(name) {
+            Some(answer) => Ok(coerce::from_toml(
+                &template.environment,
+                name,
+                variable,
+                answer,
+                Origin::AnswersFile,
+            )?),
+            None => Ok(match render_default(template, variable, values)? {
+                Some(default) => variable.string_value(&default),
+                None => variable.default_value(&template.environment),
+            }),
+        },
+    )?;
+    Ok(values)
+}
+
+impl Upgrade {
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, contents).context(format!("failed to write '{}'", path.display()))
+    }
+
+    /// Applies the changes of the template to a file of the project (`ours`),
+    /// returning what has been done to it, if anything.
+    fn upgrade_file(
+        &self,
+        base: &Path,
+        theirs: &Path,
+        ours: &Path,
+    ) -> Result<Option<&'static str>> {
+        let theirs_buf = fs::read(theirs)?;
+        let base_buf = fs::read(base).ok();
+        if !ours.exists() {
+            // files deleted from the project stay deleted
+            if base_buf.is_some() {
+                return Ok(None);
+            }
+            self.write(ours, &theirs_buf)?;
+            return Ok(Some("created"));
+        }
+        let ours_buf = fs::read(ours)?;
+        if ours_buf == theirs_buf || base_buf.as_ref() == Some(&theirs_buf) {
+            return Ok(None);
+        }
+        if base_buf.as_ref() == Some(&ours_buf) {
+            self.write(ours, &theirs_buf)?;
+            return Ok(Some("updated"));
+        }
+        let (Ok(base), Ok(ours_text), Ok(theirs)) = (
+            String::from_utf8(base_buf.unwrap_or_default()),
+            String::from_utf8(ours_buf),
+            String::from_utf8(theirs_buf),
+        ) else {
+            // binary files cannot be merged, so those of the project are kept
+            return Ok(Some("conflict"));
+        };
+        let (merged, conflicts) =
+            merge::merge3(&base, &ours_text, &theirs, ("project", "template"));
+        self.write(ours, merged.as_bytes())?;
+        Ok(Some(if conflicts > 0 { "conflict" } else { "merged" }))
+    }
+
+    pub(crate) fn run(&self, config: &Config) -> Result<()> {
+        let answers_path = self
+            .answers
+            .clone()
+            .unwrap_or_else(|| self.project.join(ANSWERS_FILE));
+        let answers = Answers::load(&answers_path)?;
+        let Some(source) = answers.header_str("__source__") else {
+            bail!(
+                "answers file does not record its template source: '{}'",
+                answers_path.display()
+            )
+        };
+        let src = Source::from_str(source)?;
+        let path = src.resolve(&config.prefix)?;
+        let new = source::load_gated_template(&path, config, self.allow)?;
+        let commit = if git::check_installed()? {
+            git::Repository::new(&new.root).head().ok()
+        } else {
+            None
+        };
+        let exported = TempDir::with_prefix("tapgen-upgrade-")
+            .context("failed to create directory for previous template")?;
+        let old = match (&self.from, answers.header_str("__commit__")) {
+            (Some(from), _) => {
+                source::load_gated_template(&from.resolve(&config.prefix)?, config, self.allow)?
+            }
+            (None, Some(recorded)) => {
+                if commit.as_deref() == Some(recorded) {
+                    println!("Project is up to date with the template (commit {recorded}).");
+                    return Ok(());
+                }
+                let repository = git::Repository::new(&new.root);
+                let toplevel = repository.toplevel()?.canonicalize()?;
+                let relative = new
+                    .path
+                    .strip_prefix(&toplevel)
+                    .context("template is not inside its git repository")?;
+                repository.export(recorded, exported.path()).context(format!(
+                    "failed to export recorded commit {recorded} (cached with --full-clone?)"
+                ))?;
+                source::load_gated_template(&exported.path().join(relative), config, self.allow)?
+            }
+            (None, None) => bail!(
+                "answers file does not record the template commit; specify the previous template with --from"
+            ),
+        };
+        println!("Upgrading '{}' from '{src}'...", self.project.display());
+
+        let old_values = resolve_answers(config, &old, &src, &answers)?;
+        let new_values = resolve_answers(config, &new, &src, &answers)?;
+        for name in new.variables.keys() {
+            if !answers.values.contains_key(name) && new_values.contains_key(name) {
+                println!("New variable '{name}' takes its default.");
+            }
+        }
+        let old_output = old
+            .generate(&old_values)
+            .context("failed to generate from previous template")?;
+        let new_output = new
+            .generate(&new_values)
+            .context("failed to generate from template")?;
+        let (old_base, new_base) = (old_output.base(), new_output.base());
+
+        let (mut changed, mut conflicts) = (0, Vec::new());
+        for entry in WalkDir::new(&new_base).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry.path().strip_prefix(&new_base)?;
+            let ours = self.project.join(name);
+            let Some(action) = self.upgrade_file(&old_base.join(name), entry.path(), &ours)? else {
+                continue;
+            };
+            println!("{action:>10} {}", name.display());
+            changed += 1;
+            if action == "conflict" {
+                conflicts.push(name.to_path_buf());
+            }
+        }
+        // files removed from the template are removed from the project, unless modified
+        for entry in WalkDir::new(&old_base).min_depth(1).sort_by_file_name() {
+            let entry = entry?;
+            let name = entry.path().strip_prefix(&old_base)?;
+            let ours = self.project.join(name);
+            if !entry.file_type().is_file() || new_base.join(name).exists() || !ours.is_file() {
+                continue;
+            }
+            if fs::read(entry.path())? == fs::read(&ours)? {
+                if !self.dry_run {
+                    fs::remove_file(&ours)
+                        .context(format!("failed to remove '{}'", ours.display()))?;
+                }
+                println!("{:>10} {}", "removed", name.display());
+                changed += 1;
+            }
+        }
+
+        println!();
+        println!("{changed} files changed, {} conflicts.", conflicts.len());
+        if self.dry_run {
+            println!("Nothing has been applied. (dry run)");
+            return Ok(());
+        }
+        Answers::save(
+            &answers_path,
+            source,
+            commit.as_deref(),
+            &new.metadata.name,
+            answers_table(&new, &new_values)?,
+        )?;
+        if !conflicts.is_empty() {
+            println!("Resolve the conflicts (marked with <<<<<<<) in:");
+            for name in &conflicts {
+                println!("│ {}", name.display());
+            }
+        }
+        Ok(())
+    }
+}
@@ -2,6 +2,8 @@ use std::path::Path;
 
 use memchr::memchr;
 
+use crate::coerce::Origin;
+
 // TODO: include path in std::io::Error
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -18,6 +20,26 @@ pub enum Error {
     },
     #[error("cannot canonicalize base path")]
     CanonicalizeBasePath(#[source] std::io::Error),
+    #[error("invalid output name: '{0}'")]
+    InvalidOutputName(String),
+    #[error("base directory is excluded from the output")]
+    ExcludedBase,
+    #[error("invalid .tapgenignore")]
+    Ignore(#[source] ignore::Error),
+    #[error("symlink '{0}' points outside of the output: '{1}'")]
+    InvalidSymlink(String, String),
+    #[error("invalid output exclude pattern: '{0}'")]
+    InvalidOutputExclude(String),
+    #[error("invalid foreach rule: {0}")]
+    InvalidForeach(String),
+    #[error("cannot resolve parent template: '{0}'")]
+    ResolveParent(String, #[source] BoxError),
+    #[error("template extends itself: '{}'", .0.display())]
+    CyclicExtends(std::path::PathBuf),
+    #[error("invalid cookiecutter template: {0}")]
+    Cookiecutter(String),
+    #[error("invalid copier template: {0}")]
+    Copier(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -30,10 +52,43 @@ pub enum InvalidVariableError {
     DefaultMismatchPattern,
     #[error("default outside choices")]
     DefaultOutsideChoices,
+    #[error("alias outside choices")]
+    AliasOutsideChoices,
+    #[error("transform not string")]
+    TransformNotString,
+    #[error("secret not string")]
+    SecretNotString,
+    #[error("default mismatch count")]
+    DefaultMismatchCount,
+    #[error("default mismatch format")]
+    DefaultMismatchFormat,
+    #[error("multiline with choices")]
+    MultilineWithChoices,
+    #[error("choices not supported")]
+    ChoicesNotSupported,
+    #[error("conflicting choices")]
+    ConflictingChoices,
+    #[error("invalid field: '{name}'")]
+    InvalidField {
+        name: String,
+        source: Box<InvalidVariableError>,
+    },
+    #[error("undefined group: '{0}'")]
+    UndefinedGroup(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid value for variable '{name}' from {origin}: {reason}")]
+pub struct CoerceError {
+    pub name: String,
+    pub origin: Origin,
+    pub reason: String,
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
 pub(crate) fn is_binary_buf(buf: &[u8]) -> bool {
     memchr(0u8, buf).is_some()
 }
@@ -44,3 +99,17 @@ pub(crate) fn path_to_string<P: AsRef<Path>>(path: P) -> String {
         .expect("path encoding should be utf-8")
         .to_string()
 }
+
+/// Creates a symlink at `link` pointing to `target`, which is a directory if `is_dir`.
+pub(crate) fn symlink(target: &Path, link: &Path, is_dir: bool) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let _ = is_dir;
+        std::os::unix::fs::symlink(target, link)
+    }
+    #[cfg(windows)]
+    match is_dir {
+        true => std::os::windows::fs::symlink_dir(target, link),
+        false => std::os::windows::fs::symlink_file(target, link),
+    }
+}
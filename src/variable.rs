@@ -1,12 +1,15 @@
+use std::collections::HashSet;
 use std::sync::OnceLock;
 
-use minijinja::{Environment, Expression};
+use indexmap::IndexMap;
+use minijinja::{Environment, Expression, Value};
 use regex::Regex;
 use serde::Deserialize;
 
+use crate::coerce;
 use crate::utils::{InvalidVariableError, Result};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 #[serde(try_from = "String")]
 pub struct Pattern(Regex);
 
@@ -30,7 +33,7 @@ impl Pattern {
 
 #[derive(Debug, Deserialize)]
 #[serde(try_from = "String")]
-pub struct Condition(Expression<'static, 'static>);
+pub struct Condition(Expression<'static, 'static>, String);
 
 impl TryFrom<String> for Condition {
     type Error = minijinja::Error;
@@ -38,16 +41,176 @@ impl TryFrom<String> for Condition {
     fn try_from(value: String) -> Result<Self, Self::Error> {
         static ENVIRONMENT: OnceLock<Environment> = OnceLock::new();
         let environment = ENVIRONMENT.get_or_init(Environment::empty);
-        Ok(Self(environment.compile_expression_owned(value)?))
+        Ok(Self(
+            environment.compile_expression_owned(value.clone())?,
+            value,
+        ))
     }
 }
 
+/// Object of a map with the values of its fields in order, by `value` of each field, leaving out
+/// those whose conditions are false against the fields valued before them.
+pub fn map_value<E>(
+    env: &Environment,
+    fields: &IndexMap<String, Variable>,
+    mut value: impl FnMut(&str, &Variable) -> std::result::Result<Value, E>,
+) -> std::result::Result<Value, E> {
+    let mut object = IndexMap::new();
+    for (name, field) in fields {
+        if let Some(condition) = &field.condition {
+            match condition.eval(env, &object) {
+                Ok(result) if !result.is_true() => continue,
+                Ok(_) => {}
+                Err(err) => log::warn!("failed to evaluate condition of field '{name}': {err}"),
+            }
+        }
+        object.insert(name.clone(), value(name, field)?);
+    }
+    Ok(Value::from_serializable(&object))
+}
+
 impl Condition {
-    pub fn eval<S: serde::Serialize>(&self, ctx: S) -> Result<minijinja::Value, minijinja::Error> {
-        self.0.eval(ctx)
+    /// Evaluates the condition in `env`, so that its filters and tests are available.
+    pub fn eval<S: serde::Serialize>(
+        &self,
+        env: &Environment,
+        ctx: S,
+    ) -> Result<minijinja::Value, minijinja::Error> {
+        env.compile_expression(&self.1)?.eval(ctx)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.1
+    }
+
+    pub fn undeclared_variables(&self) -> HashSet<String> {
+        self.0.undeclared_variables(false)
+    }
+}
+
+/// Transformation of a string answer, either a filter chain like `"trim | lower"`
+/// or an expression of `value`.
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Transform(String);
+
+impl TryFrom<String> for Transform {
+    type Error = minijinja::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        static ENVIRONMENT: OnceLock<Environment> = OnceLock::new();
+        let environment = ENVIRONMENT.get_or_init(Environment::empty);
+        let expression = environment.compile_expression(&value)?;
+        if expression.undeclared_variables(false).contains("value") {
+            return Ok(Self(value));
+        }
+        let chain = format!("value | {value}");
+        environment.compile_expression(&chain)?;
+        Ok(Self(chain))
+    }
+}
+
+impl Transform {
+    pub fn apply(&self, env: &Environment, value: &str) -> Result<String, minijinja::Error> {
+        let result = env
+            .compile_expression(&self.0)?
+            .eval(minijinja::context! { value })?;
+        Ok(result.to_string())
     }
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RawChoice {
+    Value(String),
+    Table {
+        value: String,
+        label: Option<String>,
+        #[serde(flatten)]
+        fields: IndexMap<String, toml::Value>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "RawChoice")]
+pub struct Choice {
+    pub value: String,
+    pub label: Option<String>, // shown in place of the value when prompted
+    pub fields: IndexMap<String, toml::Value>,
+}
+
+impl From<RawChoice> for Choice {
+    fn from(choice: RawChoice) -> Self {
+        match choice {
+            RawChoice::Value(value) => Self::from(value),
+            RawChoice::Table {
+                value,
+                label,
+                fields,
+            } => Self {
+                value,
+                label,
+                fields,
+            },
+        }
+    }
+}
+
+impl From<String> for Choice {
+    fn from(value: String) -> Self {
+        Self {
+            value,
+            label: None,
+            fields: IndexMap::new(),
+        }
+    }
+}
+
+impl std::fmt::Display for Choice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.label.as_ref().unwrap_or(&self.value).fmt(f)
+    }
+}
+
+impl Choice {
+    pub fn to_value(&self) -> Value {
+        if self.fields.is_empty() {
+            return Value::from(self.value.as_str());
+        }
+        let mut object = IndexMap::new();
+        object.insert("value", toml::Value::String(self.value.clone()));
+        for (key, value) in &self.fields {
+            object.insert(key, value.clone());
+        }
+        Value::from_serializable(&object)
+    }
+
+    /// The value a choice was answered by, being the `value` of a choice with fields.
+    pub fn key_of(value: &Value) -> Value {
+        match value.get_attr("value") {
+            Ok(inner) if !inner.is_undefined() => inner,
+            _ => value.clone(),
+        }
+    }
+}
+
+/// Parses choices listed one per line, each optionally followed by a tab and its label.
+pub fn parse_choices(contents: &str) -> Vec<Choice> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once('\t') {
+            Some((value, label)) => Choice {
+                value: value.trim_end().to_string(),
+                label: Some(label.trim_start().to_string()),
+                fields: IndexMap::new(),
+            },
+            None => Choice::from(line.to_string()),
+        })
+        .collect()
+}
+
 #[derive(Debug, Deserialize)]
 // #[serde(deny_unknown_fields)]
 pub struct Variable {
@@ -55,6 +218,17 @@ pub struct Variable {
     pub value: VariableValue,
     pub prompt: String,
     pub condition: Option<Condition>,
+    pub group: Option<String>,        // defined in `__groups__`
+    pub choices_cmd: Option<String>,  // producing choices at generation time
+    pub choices_file: Option<String>, // relative to the template root
+    pub transform: Option<Transform>,
+    #[serde(rename = "validate")]
+    pub validator: Option<Condition>, // of the answer as `value`
+    pub validate_message: Option<String>,
+    #[serde(default)]
+    pub secret: bool,
+    pub secret_source: Option<String>,
+    pub preview: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -63,11 +237,20 @@ pub enum VariableValue {
     String {
         default: String,
         pattern: Option<Pattern>,
-        choices: Option<Vec<String>>,
+        choices: Option<Vec<Choice>>,
+        #[serde(default)]
+        aliases: IndexMap<String, String>,
+        #[serde(default)]
+        multiline: bool,
     },
     Array {
         default: Vec<String>,
-        choices: Vec<String>,
+        #[serde(default)]
+        choices: Vec<Choice>,
+        #[serde(default)]
+        allow_custom: bool,
+        min: Option<usize>, // number of selected items
+        max: Option<usize>,
     },
     Integer {
         default: i64,
@@ -75,25 +258,209 @@ pub enum VariableValue {
     },
     Boolean {
         default: bool,
+        true_label: Option<String>,
+        false_label: Option<String>,
+        help: Option<String>,
+    },
+    Path {
+        default: String,
+        kind: PathKind,
+        #[serde(default)]
+        must_exist: bool,
     },
+    Date {
+        default: String, // or "today"
+        format: String,  // of chrono, e.g. "%Y-%m-%d"
+    },
+    Map {
+        fields: IndexMap<String, Variable>, // rendered as `{{ name.field }}`
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathKind {
+    File,
+    Dir,
+    Any,
+}
+
+impl std::fmt::Display for PathKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::File => write!(f, "file"),
+            Self::Dir => write!(f, "directory"),
+            Self::Any => write!(f, "file or directory"),
+        }
+    }
 }
 
 impl Variable {
+    pub fn default_value(&self, env: &Environment) -> Value {
+        match &self.value {
+            VariableValue::String { default, .. } => self.string_value(default),
+            VariableValue::Array { default, .. } => Value::from(default.clone()),
+            VariableValue::Integer { default, .. } => Value::from(*default),
+            VariableValue::Boolean { default, .. } => Value::from(*default),
+            VariableValue::Path { default, .. } if default.is_empty() => Value::from(""),
+            VariableValue::Path { default, .. } => {
+                Value::from(coerce::absolute_path(default).to_string_lossy())
+            }
+            VariableValue::Date { default, format } => {
+                Value::from(coerce::parse_date(format, default).unwrap_or_default())
+            }
+            VariableValue::Map { fields } => map_value(env, fields, |_, field| {
+                Ok::<_, ()>(field.default_value(env))
+            })
+            .unwrap(),
+        }
+    }
+
+    /// Value of a string answer, being the matching choice if any.
+    pub fn string_value(&self, value: &str) -> Value {
+        let VariableValue::String {
+            choices: Some(choices),
+            ..
+        } = &self.value
+        else {
+            return Value::from(value);
+        };
+        choices
+            .iter()
+            .find(|choice| choice.value == value)
+            .map(Choice::to_value)
+            .unwrap_or_else(|| Value::from(value))
+    }
+
+    /// Whether the default is rendered from previous answers, e.g. `"{{ name | slugify }}"`.
+    pub fn has_templated_default(&self) -> bool {
+        matches!(
+            &self.value,
+            VariableValue::String { default, .. } if default.contains("{{") || default.contains("{%")
+        )
+    }
+
+    /// Renders the templated default in `env`, or `None` if the default is not templated.
+    pub fn render_default<S: serde::Serialize>(
+        &self,
+        env: &Environment,
+        ctx: S,
+    ) -> Result<Option<String>, minijinja::Error> {
+        match &self.value {
+            VariableValue::String { default, .. } if self.has_templated_default() => {
+                Ok(Some(env.render_str(default, ctx)?.trim().to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the choices are only known once loaded from `choices_cmd` or `choices_file`.
+    pub fn has_dynamic_choices(&self) -> bool {
+        self.choices_cmd.is_some() || self.choices_file.is_some()
+    }
+
+    /// Replaces the choices of a string or array variable.
+    pub fn set_choices(&mut self, new: Vec<Choice>) {
+        match &mut self.value {
+            VariableValue::String { choices, .. } => *choices = Some(new),
+            VariableValue::Array { choices, .. } => *choices = new,
+            _ => {}
+        }
+    }
+
+    /// Applies the `transform` of the variable to a string answer, if any.
+    pub fn transform(&self, env: &Environment, value: &str) -> Result<String, minijinja::Error> {
+        match &self.transform {
+            Some(transform) => transform.apply(env, value),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Checks an answer against the `validate` expression, returning the message on failure.
+    pub fn check(&self, env: &Environment, value: &Value) -> Result<(), String> {
+        let Some(validator) = &self.validator else {
+            return Ok(());
+        };
+        match validator.eval(env, minijinja::context! { value }) {
+            Ok(result) if result.is_true() => Ok(()),
+            Ok(_) => Err(self
+                .validate_message
+                .clone()
+                .unwrap_or_else(|| format!("'{value}' does not satisfy `{}`", validator.as_str()))),
+            Err(err) => Err(format!("cannot evaluate `{}` ({err})", validator.as_str())),
+        }
+    }
+
+    pub fn is_secret(&self) -> bool {
+        self.secret || self.secret_source.is_some()
+    }
+
     pub fn validate(self) -> Result<Self, InvalidVariableError> {
+        if self.is_secret() && !matches!(self.value, VariableValue::String { .. }) {
+            return Err(InvalidVariableError::SecretNotString);
+        }
+        if let VariableValue::Map { fields } = self.value {
+            let fields = fields
+                .into_iter()
+                .map(|(name, field)| match field.validate() {
+                    Ok(field) => Ok((name, field)),
+                    Err(err) => Err(InvalidVariableError::InvalidField {
+                        name,
+                        source: Box::new(err),
+                    }),
+                })
+                .collect::<Result<_, _>>()?;
+            return Ok(Self {
+                value: VariableValue::Map { fields },
+                ..self
+            });
+        }
+        if self.transform.is_some() && !matches!(self.value, VariableValue::String { .. }) {
+            return Err(InvalidVariableError::TransformNotString);
+        }
+        let dynamic = self.has_dynamic_choices();
+        if dynamic {
+            let is_static = match &self.value {
+                VariableValue::String { choices, .. } => choices.is_some(),
+                VariableValue::Array { choices, .. } => !choices.is_empty(),
+                _ => return Err(InvalidVariableError::ChoicesNotSupported),
+            };
+            if is_static || (self.choices_cmd.is_some() && self.choices_file.is_some()) {
+                return Err(InvalidVariableError::ConflictingChoices);
+            }
+        }
+        let templated = self.has_templated_default();
         match &self.value {
             VariableValue::String {
                 default,
                 pattern,
                 choices,
+                aliases,
+                multiline,
             } => {
+                // templated defaults can only be checked once rendered
+                let default = if templated { "" } else { default.as_str() };
+                if dynamic && *multiline {
+                    return Err(InvalidVariableError::MultilineWithChoices);
+                }
                 if let Some(choices) = choices {
+                    if *multiline {
+                        return Err(InvalidVariableError::MultilineWithChoices);
+                    }
+                    if aliases
+                        .values()
+                        .any(|alias| !choices.iter().any(|choice| choice.value == *alias))
+                    {
+                        return Err(InvalidVariableError::AliasOutsideChoices);
+                    }
                     if pattern.is_some() {
                         return Err(InvalidVariableError::PatternWithChoices);
                     }
                     if choices.is_empty() {
                         return Err(InvalidVariableError::DefaultOutsideChoices);
                     }
-                    if !default.is_empty() && !choices.iter().any(|choice| choice == default) {
+                    if !default.is_empty() && !choices.iter().any(|choice| choice.value == default)
+                    {
                         return Err(InvalidVariableError::DefaultOutsideChoices);
                     }
                 } else if let Some(pattern) = pattern {
@@ -102,11 +469,39 @@ impl Variable {
                     }
                 }
             }
-            VariableValue::Array { default, choices } => {
-                if !default.is_empty() && default.iter().any(|d| !choices.contains(d)) {
+            VariableValue::Array {
+                default,
+                choices,
+                allow_custom,
+                min,
+                max,
+            } => {
+                if let (Some(min), Some(max)) = (min, max) {
+                    if min > max {
+                        return Err(InvalidVariableError::UnreasonableRange);
+                    }
+                }
+                if !allow_custom && !dynamic && min.is_some_and(|min| min > choices.len()) {
+                    return Err(InvalidVariableError::UnreasonableRange);
+                }
+                if coerce::check_count(*min, *max, default.len()).is_err() {
+                    return Err(InvalidVariableError::DefaultMismatchCount);
+                }
+                // dynamic choices are checked against the default when prompted
+                if !allow_custom
+                    && !dynamic
+                    && default
+                        .iter()
+                        .any(|d| !choices.iter().any(|choice| choice.value == *d))
+                {
                     return Err(InvalidVariableError::DefaultOutsideChoices);
                 }
             }
+            VariableValue::Date { default, format } => {
+                if coerce::parse_date(format, default).is_err() {
+                    return Err(InvalidVariableError::DefaultMismatchFormat);
+                }
+            }
             VariableValue::Integer {
                 default,
                 range: Some((min, max)),
@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context as _, Result};
+use glob::Pattern;
+
+use crate::prompt;
+
+enum Manifest {
+    Cargo(PathBuf),
+    Pnpm(PathBuf),
+    Npm(PathBuf),
+}
+
+impl Manifest {
+    /// Workspace manifests in `root` that the package at `target` can be registered in.
+    fn detect(root: &Path, target: &Path) -> Vec<Self> {
+        let mut manifests = Vec::new();
+        let cargo = root.join("Cargo.toml");
+        if target.join("Cargo.toml").exists()
+            && fs::read_to_string(&cargo).is_ok_and(|contents| contents.contains("[workspace"))
+        {
+            manifests.push(Self::Cargo(cargo));
+        }
+        if target.join("package.json").exists() {
+            let pnpm = root.join("pnpm-workspace.yaml");
+            let npm = root.join("package.json");
+            if pnpm.exists() {
+                manifests.push(Self::Pnpm(pnpm));
+            } else if fs::read_to_string(&npm)
+                .is_ok_and(|contents| contents.contains("\"workspaces\""))
+            {
+                manifests.push(Self::Npm(npm));
+            }
+        }
+        manifests
+    }
+
+    fn path(&self) -> &Path {
+        match self {
+            Self::Cargo(path) | Self::Pnpm(path) | Self::Npm(path) => path,
+        }
+    }
+
+    /// Adds `member` to the manifest, returning false if it is already covered by a member.
+    fn register(&self, member: &str) -> Result<bool> {
+        let contents = fs::read_to_string(self.path())?;
+        let contents = match self {
+            Self::Cargo(_) => register_cargo(&contents, member)?,
+            Self::Pnpm(_) => register_pnpm(&contents, member)?,
+            Self::Npm(_) => register_npm(&contents, member)?,
+        };
+        let Some(contents) = contents else {
+            return Ok(false);
+        };
+        fs::write(self.path(), contents)?;
+        Ok(true)
+    }
+}
+
+fn is_covered<'a>(mut members: impl Iterator<Item = &'a str>, member: &str) -> bool {
+    members.any(|pattern| {
+        let pattern = pattern.trim_start_matches("./").trim_end_matches('/');
+        Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(member))
+    })
+}
+
+fn register_cargo(contents: &str, member: &str) -> Result<Option<String>> {
+    let mut document = contents.parse::<toml_edit::Document>()?;
+    let Some(workspace) = document
+        .get_mut("workspace")
+        .and_then(|workspace| workspace.as_table_like_mut())
+    else {
+        bail!("no [workspace] table")
+    };
+    let members = workspace
+        .entry("members")
+        .or_insert(toml_edit::value(toml_edit::Array::new()));
+    let Some(members) = members.as_array_mut() else {
+        bail!("workspace.members is not an array")
+    };
+    if is_covered(members.iter().filter_map(|m| m.as_str()), member) {
+        return Ok(None);
+    }
+    members.push(member);
+    Ok(Some(document.to_string()))
+}
+
+fn register_pnpm(contents: &str, member: &str) -> Result<Option<String>> {
+    let mut lines = contents.lines().map(String::from).collect::<Vec<_>>();
+    let Some(start) = lines.iter().position(|line| line.starts_with("packages:")) else {
+        let mut contents = contents.to_string();
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
+        contents.push_str(&format!("packages:\n  - '{member}'\n"));
+        return Ok(Some(contents));
+    };
+    if lines[start].trim_end() != "packages:" {
+        bail!("inline packages list is not supported")
+    }
+    // entries of the block sequence, among blank lines and comments
+    let entries = lines[start + 1..]
+        .iter()
+        .take_while(|line| line.trim().is_empty() || line.starts_with([' ', '\t', '-', '#']))
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with('-'))
+        .collect::<Vec<_>>();
+    let values = entries
+        .iter()
+        .map(|(_, line)| line.trim_start()[1..].trim().trim_matches(['\'', '"']));
+    if is_covered(values, member) {
+        return Ok(None);
+    }
+    let (at, indent) = match entries.last() {
+        Some((i, line)) => (
+            start + i + 2,
+            line[..line.len() - line.trim_start().len()].to_string(),
+        ),
+        None => (start + 1, String::from("  ")),
+    };
+    lines.insert(at, format!("{indent}- '{member}'"));
+    Ok(Some(lines.join("\n") + "\n"))
+}
+
+fn register_npm(contents: &str, member: &str) -> Result<Option<String>> {
+    let mut package = serde_json::from_str::<serde_json::Value>(contents)?;
+    let workspaces = match package.get_mut("workspaces") {
+        Some(serde_json::Value::Object(workspaces)) => workspaces.get_mut("packages"),
+        workspaces => workspaces,
+    };
+    let Some(serde_json::Value::Array(workspaces)) = workspaces else {
+        bail!("workspaces is not an array")
+    };
+    if is_covered(workspaces.iter().filter_map(|w| w.as_str()), member) {
+        return Ok(None);
+    }
+    workspaces.push(serde_json::Value::from(member));
+    Ok(Some(serde_json::to_string_pretty(&package)? + "\n"))
+}
+
+/// Registers the package generated at `target` in the nearest workspace manifests above it.
+pub(crate) fn register(target: &Path) -> Result<()> {
+    let target = target
+        .canonicalize()
+        .context(format!("failed to resolve path: '{}'", target.display()))?;
+    for root in target.ancestors().skip(1) {
+        let manifests = Manifest::detect(root, &target);
+        if manifests.is_empty() {
+            continue;
+        }
+        let member = target
+            .strip_prefix(root)
+            .unwrap()
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        for manifest in manifests {
+            let path = manifest.path();
+            if !prompt::confirm(
+                format!("Register '{member}' in workspace '{}'?", path.display()),
+                Some(true),
+            ) {
+                continue;
+            }
+            match manifest.register(&member) {
+                Ok(true) => {
+                    log::info!("registered '{member}' in '{}'", path.display());
+                    say!("Registered '{member}' in '{}'.", path.display());
+                }
+                Ok(false) => say!("'{member}' is already a member of '{}'.", path.display()),
+                Err(err) => {
+                    log::warn!("cannot register in '{}': {err:#}", path.display());
+                    say!("Warning: cannot register in '{}': {err:#}", path.display());
+                }
+            }
+        }
+        return Ok(());
+    }
+    say!("No workspace found above '{}'.", target.display());
+    Ok(())
+}